@@ -0,0 +1,38 @@
+//! Compares the blocked matmul kernel against the original element-at-a-time
+//! implementation. Run with `cargo bench`, or `cargo bench --features parallel`
+//! to include the multithreaded path.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rusty_grad::tensor::Tensor;
+
+fn time(mut f: impl FnMut(), iters: u32) -> Duration {
+    f(); // warm up
+    let start = Instant::now();
+    for _ in 0..iters {
+        f();
+    }
+    start.elapsed() / iters
+}
+
+fn main() {
+    println!("{:>6} {:>14} {:>14} {:>9}", "size", "naive", "blocked", "speedup");
+
+    for size in [64, 128, 256, 512] {
+        let l = Tensor::rand(&[size, size]);
+        let r = Tensor::rand(&[size, size]);
+
+        let iters = if size >= 256 { 1 } else { 5 };
+        let naive = time(|| drop(black_box(l.matmul_naive(&r))), iters);
+        let blocked = time(|| drop(black_box(l.matmul(&r))), iters * 4);
+
+        println!(
+            "{:>6} {:>12.2?} {:>12.2?} {:>8.1}x",
+            size,
+            naive,
+            blocked,
+            naive.as_secs_f64() / blocked.as_secs_f64()
+        );
+    }
+}
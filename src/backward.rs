@@ -1,6 +1,10 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use crate::node::{BinaryOpResult, Node, ReduceOpResult, UnaryOpResult};
+use crate::ops::{add, sum_to};
+use crate::node::{BinaryOpResult, NaryOpResult, Node, Placeholder, ReduceOpResult, UnaryOpResult};
 use crate::tensor::Tensor;
 
 #[derive(Debug)]
@@ -19,6 +23,11 @@ pub struct ReduceOpTrace {
     arg: Box<DTrace>,
 }
 
+#[derive(Debug)]
+pub struct NaryOpTrace {
+    args: Vec<DTrace>,
+}
+
 
 #[derive(Debug)]
 pub struct DParamDX {
@@ -31,12 +40,13 @@ pub enum DTrace {
     BinOp(BinOpTrace),
     UnaryOp(UnaryOpTrace),
     ReduceOp(ReduceOpTrace),
+    NaryOp(NaryOpTrace),
     DParamDX(DParamDX),
 }
 
 impl Node {
     pub fn backwards(&self) -> DTrace {
-        self.back_impl(Rc::new(Tensor::from(1.)))
+        self.back_impl(Rc::new(seed(&self.val())))
     }
 
     pub fn back_impl(&self, upstream: Rc<Tensor>) -> DTrace {
@@ -44,20 +54,31 @@ impl Node {
             Node::BinaryOp(res) => res.back(upstream),
             Node::UnaryOp(res) => res.back(upstream),
             Node::ReduceOp(res) => res.back(upstream),
-            Node::TensorParam(_t, name) => DTrace::DParamDX(DParamDX {
-                d_val: upstream.clone(),
+            Node::NaryOp(res) => res.back(upstream),
+            Node::TensorParam(t, name) => DTrace::DParamDX(DParamDX {
+                d_val: Rc::new(param_grad(t, &upstream)),
                 param_name: name,
             }),
+            Node::Placeholder(p) => DTrace::DParamDX(DParamDX {
+                d_val: Rc::new(param_grad(&p.value.realized(), &upstream)),
+                param_name: p.name,
+            }),
+            Node::Const(_) => DTrace::NaryOp(NaryOpTrace { args: vec![] }),
         }
     }
 }
 
 impl BinaryOpResult {
+    /// Gradients for both arguments, summed back over any dimensions that were
+    /// broadcast in the forward pass so each matches its argument's shape.
+    fn arg_grads(&self, upstream: Rc<Tensor>) -> (Rc<Tensor>, Rc<Tensor>) {
+        let (l, r) = (Rc::new(self.args.0.val()), Rc::new(self.args.1.val()));
+        let (g_l, g_r) = self.op.get_grads(upstream, (l.clone(), r.clone()));
+        (unbroadcast_grad(self.op.name(), g_l, &l), unbroadcast_grad(self.op.name(), g_r, &r))
+    }
+
     fn back(&self, upstream: Rc<Tensor>) -> DTrace {
-        let (g_l, g_r) = self.op.get_grads(
-            upstream,
-            (Rc::new(self.args.0.val()), Rc::new(self.args.1.val())),
-        );
+        let (g_l, g_r) = self.arg_grads(upstream);
         DTrace::BinOp(BinOpTrace {
             arg1: Box::new(self.args.0.back_impl(g_l)),
             arg2: Box::new(self.args.1.back_impl(g_r)),
@@ -66,9 +87,27 @@ impl BinaryOpResult {
     }
 }
 
+fn unbroadcast_grad(op_name: &str, grad: Rc<Tensor>, arg: &Tensor) -> Rc<Tensor> {
+    if grad.size() == arg.size() {
+        return grad;
+    }
+    Rc::new(grad.unbroadcast(arg.size()).unwrap_or_else(|_| {
+        panic!(
+            "{} produced a gradient of shape {:?} for an argument of shape {:?}",
+            op_name,
+            grad.size(),
+            arg.size()
+        )
+    }))
+}
+
 impl UnaryOpResult {
+    fn arg_grad(&self, upstream: Rc<Tensor>) -> Rc<Tensor> {
+        self.op.get_grads(upstream, Rc::new(self.arg.val()), Rc::new(self.value.realized()))
+    }
+
     fn back(&self, upstream: Rc<Tensor>) -> DTrace {
-        let g = self.op.get_grads(upstream.clone(), Rc::new(self.arg.val()));
+        let g = self.arg_grad(upstream);
         DTrace::UnaryOp(UnaryOpTrace {
             arg: Box::new(self.arg.back_impl(g))
         })
@@ -84,19 +123,254 @@ impl ReduceOpResult {
     }
 }
 
+impl NaryOpResult {
+    fn arg_grads(&self, upstream: Rc<Tensor>) -> Vec<Rc<Tensor>> {
+        let args = self.args.iter().map(|arg| Rc::new(arg.val())).collect();
+        let grads = self.op.get_grads(upstream, args);
+        assert_eq!(
+            grads.len(),
+            self.args.len(),
+            "{} returned {} gradients for {} arguments",
+            self.op.name(),
+            grads.len(),
+            self.args.len()
+        );
+        grads
+    }
+
+    fn back(&self, upstream: Rc<Tensor>) -> DTrace {
+        let grads = self.arg_grads(upstream);
+        DTrace::NaryOp(NaryOpTrace {
+            args: std::iter::zip(&self.args, grads)
+                .map(|(arg, g)| arg.back_impl(g))
+                .collect(),
+        })
+    }
+}
+
 pub type GradMap = HashMap<String, Tensor>;
 
+/// parameter name -> gradient graph, see `Node::grad_nodes`
+pub type NodeGradMap = HashMap<String, Rc<Node>>;
+
 #[macro_export]
 macro_rules! grad {
     ($forward_fn:expr, $($arg:expr),*) => {
         {
             let output = $forward_fn($($arg),*);
-            let dtrace = output.backwards();
-            (output.val(), accum_grads(dtrace))
+            (output.val(), output.grads())
         }
     };
 }
 
+impl Node {
+    /// Reverse-mode pass over the graph treated as a DAG rather than a tree.
+    ///
+    /// `backwards` recurses once per *path* to each node, so a node shared by
+    /// several consumers has its whole subgraph differentiated repeatedly.
+    /// Here we topologically sort the nodes once (by pointer identity), sum the
+    /// upstream gradients flowing into each node, and visit every node exactly
+    /// once. The result is the same `GradMap` as `accum_grads(self.backwards())`.
+    pub fn grads(&self) -> GradMap {
+        let order = topo_sort(self);
+
+        let mut upstreams: HashMap<*const Node, Rc<Tensor>> = HashMap::new();
+        upstreams.insert(self as *const Node, Rc::new(seed(&self.val())));
+
+        let mut map = GradMap::new();
+
+        // `order` has every node after all of its arguments, so walking it
+        // backwards guarantees a node's upstream is complete before it's used
+        for node in order.into_iter().rev() {
+            let Some(upstream) = upstreams.remove(&(node as *const Node)) else {
+                continue;
+            };
+
+            match node {
+                Node::BinaryOp(res) => {
+                    let (g_l, g_r) = res.arg_grads(upstream);
+                    accum_upstream(&mut upstreams, &res.args.0, g_l);
+                    accum_upstream(&mut upstreams, &res.args.1, g_r);
+                }
+                Node::UnaryOp(res) => {
+                    let g = res.arg_grad(upstream);
+                    accum_upstream(&mut upstreams, &res.arg, g);
+                }
+                Node::ReduceOp(res) => {
+                    let g = res.op.get_grads(upstream, Rc::new(res.arg.val()));
+                    accum_upstream(&mut upstreams, &res.arg, g);
+                }
+                Node::NaryOp(res) => {
+                    for (arg, g) in std::iter::zip(&res.args, res.arg_grads(upstream)) {
+                        accum_upstream(&mut upstreams, arg, g);
+                    }
+                }
+                Node::TensorParam(t, name) => accum_param(&mut map, name, &param_grad(t, &upstream)),
+                Node::Placeholder(p) => accum_param(&mut map, p.name, &param_grad(&p.value.realized(), &upstream)),
+                Node::Const(_) => {}
+            }
+        }
+
+        map
+    }
+
+    /// Like `grads`, but every gradient is itself a graph built from the ops'
+    /// `grad_graph` rules, so it can be differentiated again: `grad(grad(f))`,
+    /// Hessian-vector products, gradient penalties in a loss, etc.
+    ///
+    /// A parameter's gradient node depends on the same `TensorParam`s as `self`.
+    /// Unlike `grads`, gradients aren't cast back to the parameter's dtype.
+    pub fn grad_nodes(self: &Rc<Self>) -> NodeGradMap {
+        let order = topo_sort(self);
+        let rcs = node_rcs(self, &order);
+
+        let mut upstreams: HashMap<*const Node, Rc<Node>> = HashMap::new();
+        upstreams.insert(Rc::as_ptr(self), Rc::new(Node::Const(seed(&self.val()))));
+
+        let mut map = NodeGradMap::new();
+
+        for node in order.into_iter().rev() {
+            let Some(upstream) = upstreams.remove(&(node as *const Node)) else {
+                continue;
+            };
+            let out = rcs[&(node as *const Node)].clone();
+
+            match node {
+                Node::BinaryOp(res) => {
+                    let (g_l, g_r) = res.op.grad_graph(upstream, res.args.clone(), out);
+                    let g_l = sum_to(g_l, res.args.0.val().size());
+                    let g_r = sum_to(g_r, res.args.1.val().size());
+                    accum_upstream_node(&mut upstreams, &res.args.0, g_l);
+                    accum_upstream_node(&mut upstreams, &res.args.1, g_r);
+                }
+                Node::UnaryOp(res) => {
+                    let g = res.op.grad_graph(upstream, res.arg.clone(), out);
+                    accum_upstream_node(&mut upstreams, &res.arg, g);
+                }
+                Node::ReduceOp(res) => {
+                    let g = res.op.grad_graph(upstream, res.arg.clone(), out);
+                    accum_upstream_node(&mut upstreams, &res.arg, g);
+                }
+                Node::NaryOp(res) => {
+                    let grads = res.op.grad_graph(upstream, res.args.clone(), out);
+                    assert_eq!(grads.len(), res.args.len(), "{} returned {} gradients for {} arguments", res.op.name(), grads.len(), res.args.len());
+                    for (arg, g) in std::iter::zip(&res.args, grads) {
+                        accum_upstream_node(&mut upstreams, arg, g);
+                    }
+                }
+                Node::TensorParam(_, name) | Node::Placeholder(Placeholder { name, .. }) => {
+                    let summed = match map.remove(*name) {
+                        Some(current) => add(current, upstream),
+                        None => upstream,
+                    };
+                    map.insert(name.to_string(), summed);
+                }
+                Node::Const(_) => {}
+            }
+        }
+
+        map
+    }
+}
+
+/// `topo_sort` hands out plain references, this gets back the Rcs (e.g. to
+/// build new nodes on top of old ones)
+fn node_rcs(root: &Rc<Node>, order: &[&Node]) -> HashMap<*const Node, Rc<Node>> {
+    let mut rcs: HashMap<*const Node, Rc<Node>> = HashMap::new();
+    rcs.insert(Rc::as_ptr(root), root.clone());
+    for node in order {
+        for arg in node.args() {
+            rcs.insert(Rc::as_ptr(arg), arg.clone());
+        }
+    }
+    rcs
+}
+
+fn accum_upstream_node(upstreams: &mut HashMap<*const Node, Rc<Node>>, node: &Rc<Node>, grad: Rc<Node>) {
+    let key = Rc::as_ptr(node);
+    let summed = match upstreams.remove(&key) {
+        Some(current) => add(current, grad),
+        None => grad,
+    };
+    upstreams.insert(key, summed);
+}
+
+/// Post-order DFS over the graph, deduplicating nodes by pointer, so every node
+/// comes after its arguments. Iterative so that deep graphs don't blow the stack.
+pub(crate) fn topo_sort(root: &Node) -> Vec<&Node> {
+    let mut order: Vec<&Node> = vec![];
+    let mut visited: HashSet<*const Node> = HashSet::new();
+
+    // (node, whether its arguments have already been pushed)
+    let mut stack: Vec<(&Node, bool)> = vec![(root, false)];
+
+    while let Some((node, expanded)) = stack.pop() {
+        if expanded {
+            order.push(node);
+            continue;
+        }
+        if !visited.insert(node as *const Node) {
+            continue;
+        }
+
+        stack.push((node, true));
+        for arg in node.args() {
+            if !visited.contains(&Rc::as_ptr(arg)) {
+                stack.push((arg, false));
+            }
+        }
+    }
+
+    order
+}
+
+fn accum_upstream(upstreams: &mut HashMap<*const Node, Rc<Tensor>>, node: &Rc<Node>, grad: Rc<Tensor>) {
+    let key = Rc::as_ptr(node);
+    let summed = match upstreams.remove(&key) {
+        Some(current) => Rc::new(Tensor::add(&current, &grad).unwrap_or_else(|_| {
+            panic!(
+                "could not add tensors with shapes {:?} and {:?}",
+                current.size(),
+                grad.size()
+            )
+        })),
+        None => grad,
+    };
+    upstreams.insert(key, summed);
+}
+
+/// d output / d output, in the output's (floating point) dtype
+fn seed(output: &Tensor) -> Tensor {
+    Tensor::ones(output.size()).to_dtype(output.dtype().to_float())
+}
+
+/// Intermediate gradients may end up in a wider dtype than the parameter (e.g.
+/// an f32 parameter meeting an f64 constant), so floating point parameters
+/// get theirs converted back. An f32 model keeps f32 gradients.
+fn param_grad(param: &Tensor, grad: &Tensor) -> Tensor {
+    if param.dtype().is_float() {
+        grad.to_dtype(param.dtype())
+    } else {
+        grad.clone()
+    }
+}
+
+/// Sum gradients for the same parameter (by name), starting from zeros of the
+/// gradient's dtype so an f32 parameter's gradient stays f32
+fn accum_param(map: &mut GradMap, name: &str, grad: &Tensor) {
+    let current_value = map
+        .entry(name.to_string())
+        .or_insert_with(|| Tensor::zeros(grad.size()).to_dtype(grad.dtype()));
+
+    *current_value = Tensor::add(current_value, grad).unwrap_or_else(|_| {
+        panic!(
+            "could not add tensors with shapes {:?} and {:?}",
+            current_value.size(),
+            grad.size()
+        )
+    });
+}
+
 pub fn accum_grads(node: DTrace) -> GradMap {
     let mut map = GradMap::new();
     _accum_grads(&node, &mut map);
@@ -113,26 +387,20 @@ fn _accum_grads(node: &DTrace, map: &mut GradMap) {
         }
         DTrace::UnaryOp(op) => _accum_grads(&op.arg, map),
         DTrace::ReduceOp(op) => _accum_grads(&op.arg, map),
-        DTrace::DParamDX(param) => {
-            let name = param.param_name.to_string();
-
-            let current_value = map.entry(name).or_insert(Tensor::from(0.));
-
-            *current_value = Tensor::add(current_value, &param.d_val).unwrap_or_else(|_| {
-                panic!(
-                    "could not add tensors with shapes {:?} and {:?}",
-                    current_value.size(),
-                    param.d_val.size()
-                )
-            });
-
-        }
+        DTrace::NaryOp(op) => op.args.iter().for_each(|arg| _accum_grads(arg, map)),
+        DTrace::DParamDX(param) => accum_param(map, param.param_name, &param.d_val),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ops::{add, mean, mul};
+    use crate::ops::{
+        abs, add, cat, chunk, cos, exp, expand, flatten, gather, index_select, log, masked_select, max, maximum, mean,
+        minimum, mmul, mul, permute, pow, prod, reciprocal, rsqrt, scatter_add, sin, slice, split, sqr, sqrt, stack, sum,
+        unsqueeze,
+    };
+    use crate::dtype::DType;
+    use crate::tensor::SliceRange;
     use super::*;
 
     #[test]
@@ -156,7 +424,7 @@ mod tests {
         fn forward(a: Tensor, b: Tensor) -> Rc<Node> {
             let a = Rc::new(Node::TensorParam(a, "a"));
             let b = Rc::new(Node::TensorParam(b, "b"));
-            mul(mean(a), b)
+            mul(mean(a, &[], false), b)
         }
 
         let a = Tensor::from(&[1., 2., 3., 4.] as &[f64]);
@@ -168,13 +436,13 @@ mod tests {
         assert_eq!(grads_map.len(), 2);
 
         // gradient of x:
-        // ∂x_i / mul(mean(a), b) = 1/len(a) * b
+        // ∂x_i / mul(mean(a, &[], false), b) = 1/len(a) * b
         //                        = 1/4 * 8
         //                        = 2
-        assert_eq!(grads_map.get("a").unwrap().data, &[2., 2., 2., 2.]);
+        assert_eq!(grads_map.get("a").unwrap().to_vec(), &[2., 2., 2., 2.]);
 
         // gradient of y:
-        // ∂y / mul(mean(a), b) = mean(a)
+        // ∂y / mul(mean(a, &[], false), b) = mean(a)
         //                      = 2.5
         assert_eq!(grads_map.get("b").unwrap().item().unwrap(), 2.5);
 
@@ -182,4 +450,389 @@ mod tests {
         // ∂x_i / mean(x) = 1/len(x)
         // ∂x / x * y = y
     }
+
+    #[test]
+    fn test_grads_shared_node() {
+        // `c` is consumed twice, so the tree-shaped pass walks its subgraph twice
+        fn forward(a: Tensor, b: Tensor) -> Rc<Node> {
+            let a = Rc::new(Node::TensorParam(a, "a"));
+            let b = Rc::new(Node::TensorParam(b, "b"));
+            let c = mul(a, b.clone());
+            add(mul(c.clone(), c), b)
+        }
+
+        let a = Tensor::from(3.);
+        let b = Tensor::from(2.);
+
+        let out = forward(a.clone(), b.clone());
+        let tree_grads = accum_grads(out.backwards());
+        let (_val, dag_grads) = grad!(forward, a, b);
+
+        // d/da (ab)^2 + b = 2ab^2 = 24
+        // d/db (ab)^2 + b = 2a^2b + 1 = 37
+        assert_eq!(dag_grads.get("a").unwrap().item().unwrap(), 24.);
+        assert_eq!(dag_grads.get("b").unwrap().item().unwrap(), 37.);
+        for name in ["a", "b"] {
+            assert_eq!(
+                dag_grads.get(name).unwrap().item().unwrap(),
+                tree_grads.get(name).unwrap().item().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_grads_deep_residual() {
+        // x_{i+1} = x_i + x_i, 64 times. exponential as a tree, linear as a DAG.
+        let mut x = Rc::new(Node::TensorParam(Tensor::from(1.), "x"));
+        for _ in 0..64 {
+            x = add(x.clone(), x);
+        }
+
+        let grads = x.grads();
+        assert_eq!(grads.get("x").unwrap().item().unwrap(), 2f64.powi(64));
+    }
+
+    #[test]
+    fn test_grads_broadcast_bias() {
+        fn forward(x: Tensor, b: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let b = Rc::new(Node::TensorParam(b, "b"));
+            mean(mul(add(x, b.clone()), b), &[], false)
+        }
+
+        let x = Tensor::ones(&[5, 4]);
+        let b = Tensor::from(&[1., 2., 3., 4.] as &[f64]);
+
+        let (_val, grads_map) = grad!(forward, x, b);
+
+        assert_eq!(grads_map.get("x").unwrap().size(), &[5, 4]);
+        assert_eq!(grads_map.get("b").unwrap().size(), &[4]);
+
+        // mean over 20 elements of (x + b) * b
+        // ∂/∂b_j = sum_i (x_ij + 2 b_j) / 20 = 5 * (1 + 2 b_j) / 20
+        assert_eq!(grads_map.get("b").unwrap().to_vec(), &[0.75, 1.25, 1.75, 2.25]);
+    }
+
+    #[test]
+    fn test_grads_axis_reductions() {
+        fn forward(x: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            // per-row max, per-column product, summed together
+            let row_max = max(x.clone(), &[1], true);
+            let col_prod = prod(x, &[0], false);
+            add(sum(row_max, &[], false), sum(col_prod, &[], false))
+        }
+
+        let x = Tensor::new(vec![1., 4., 2., 3., 0., 5.], &[2, 3]);
+
+        let (val, grads_map) = grad!(forward, x);
+
+        // row maxes: 4 + 5, column products: 3 + 0 + 10
+        assert_eq!(val.item().unwrap(), 22.);
+        assert_eq!(grads_map.get("x").unwrap().to_vec(), &[3., 1., 5., 1., 4., 3.]);
+    }
+
+    #[test]
+    fn test_grads_batched_mmul() {
+        // a batch of 4 sequences of length 2, projected by a shared [3, 2] weight,
+        // then a shared vector
+        fn forward(x: Tensor, w: Tensor, v: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let w = Rc::new(Node::TensorParam(w, "w"));
+            let v = Rc::new(Node::TensorParam(v, "v"));
+            sum(mmul(mmul(x, w), v), &[], false)
+        }
+
+        let x = Tensor::ones(&[4, 2, 3]);
+        let w = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[3, 2]);
+        let v = Tensor::from(&[1., -1.] as &[f64]);
+
+        let (val, grads_map) = grad!(forward, x, w, v);
+
+        // every row of x @ w is [9, 12], dotted with v gives -3, over 8 rows
+        assert_eq!(val.item().unwrap(), -24.);
+
+        let g_x = grads_map.get("x").unwrap();
+        assert_eq!(g_x.size(), &[4, 2, 3]);
+        // ∂/∂x = v @ w^T for every row
+        assert_eq!(g_x.sum(&[0, 1], false).to_vec(), vec![-8., -8., -8.]);
+
+        // ∂/∂w = sum over rows of x^T v, summed across the broadcast batch
+        let g_w = grads_map.get("w").unwrap();
+        assert_eq!(g_w.size(), &[3, 2]);
+        assert_eq!(g_w.to_vec(), vec![8., -8., 8., -8., 8., -8.]);
+
+        // ∂/∂v = sum over rows of x @ w
+        assert_eq!(grads_map.get("v").unwrap().to_vec(), vec![72., 96.]);
+    }
+
+    #[test]
+    fn test_grads_shape_ops() {
+        fn forward(x: Tensor, w: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let w = Rc::new(Node::TensorParam(w, "w"));
+            // [2, 3, 4] -> [4, 2, 3] -> [4, 6], weighted by w broadcast from [6] up to [4, 6]
+            let x = flatten(permute(x, &[2, 0, 1]), 1, 2);
+            let w = expand(unsqueeze(w, 0), &[4, 6]);
+            sum(mul(x, w), &[], false)
+        }
+
+        let x = Tensor::new((0..24).map(|i| i as f64).collect(), &[2, 3, 4]);
+        let w = Tensor::from(&[1., 2., 3., 4., 5., 6.] as &[f64]);
+
+        let (_val, grads_map) = grad!(forward, x, w);
+
+        // x[i, j, k] meets w[i * 3 + j]
+        let g_x = grads_map.get("x").unwrap();
+        assert_eq!(g_x.size(), &[2, 3, 4]);
+        assert_eq!(g_x.at(&vec![1, 2, 3]).unwrap(), 6.);
+        assert_eq!(g_x.at(&vec![0, 1, 0]).unwrap(), 2.);
+
+        // w[i * 3 + j] meets x[i, j, :], summed
+        let g_w = grads_map.get("w").unwrap();
+        assert_eq!(g_w.size(), &[6]);
+        assert_eq!(g_w.to_vec(), vec![6., 22., 38., 54., 70., 86.]);
+    }
+
+    #[test]
+    fn test_grads_indexing() {
+        fn forward(x: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let cols = slice(x.clone(), &[(..).into(), SliceRange::from(1..).step_by(2)]);
+            let rows = index_select(x.clone(), 0, &[1, 1]);
+            let picked = gather(x.clone(), 1, &Tensor::from_vec(vec![0i64, 2], &[2, 1]));
+            let masked = masked_select(x, &Tensor::new(vec![0., 0., 0., 1.], &[4]));
+            let total = add(sum(cols, &[], false), sum(rows, &[], false));
+            add(total, add(sum(picked, &[], false), sum(masked, &[], false)))
+        }
+
+        let x = Tensor::new((0..8).map(|i| i as f64).collect(), &[2, 4]);
+        let (_val, grads_map) = grad!(forward, x);
+
+        // cols hits columns 1 and 3, rows hits row 1 twice, gather hits [0, 0] and [1, 2],
+        // and the mask hits column 3
+        assert_eq!(
+            grads_map.get("x").unwrap().to_vec(),
+            vec![1., 1., 0., 2., 2., 3., 3., 4.]
+        );
+    }
+
+    #[test]
+    fn test_grads_scatter_add() {
+        fn forward(base: Tensor, src: Tensor) -> Rc<Node> {
+            let base = Rc::new(Node::TensorParam(base, "base"));
+            let src = Rc::new(Node::TensorParam(src, "src"));
+            let index = Tensor::from_vec(vec![2i64, 2, 0], &[3]);
+            let w = Rc::new(Node::TensorParam(Tensor::from(&[1., 2., 3.] as &[f64]), "w"));
+            sum(mul(scatter_add(base, 0, &index, src), w), &[], false)
+        }
+
+        let (_val, grads_map) = grad!(forward, Tensor::zeros(&[3]), Tensor::ones(&[3]));
+
+        assert_eq!(grads_map.get("base").unwrap().to_vec(), vec![1., 2., 3.]);
+        assert_eq!(grads_map.get("src").unwrap().to_vec(), vec![3., 3., 1.]);
+    }
+
+    #[test]
+    fn test_grads_cat_stack() {
+        fn forward(a: Tensor, b: Tensor) -> Rc<Node> {
+            let a = Rc::new(Node::TensorParam(a, "a"));
+            let b = Rc::new(Node::TensorParam(b, "b"));
+            let w = Rc::new(Node::TensorParam(Tensor::new((1..=6).map(|i| i as f64).collect(), &[2, 3]), "w"));
+
+            // [2, 2] ++ [2, 1] -> [2, 3], weighted elementwise
+            let joined = mul(cat(&[a.clone(), b], 1), w);
+            // [2, 2] and itself stacked -> [2, 2, 2], the second copy weighted by 10
+            let stacked = stack(&[a.clone(), a], 0);
+            let ten = Rc::new(Node::TensorParam(Tensor::new(vec![1., 10.], &[2, 1, 1]), "ten"));
+            add(sum(joined, &[], false), sum(mul(stacked, ten), &[], false))
+        }
+
+        let (_val, grads_map) = grad!(forward, Tensor::ones(&[2, 2]), Tensor::ones(&[2, 1]));
+
+        assert_eq!(grads_map.get("a").unwrap().to_vec(), vec![12., 13., 15., 16.]);
+        assert_eq!(grads_map.get("b").unwrap().to_vec(), vec![3., 6.]);
+    }
+
+    #[test]
+    fn test_grads_split() {
+        fn forward(x: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let pieces = split(x.clone(), 2, 0);
+            let halves = chunk(x, 2, 0);
+            // weight the 3 pieces of 2, 2, 1 by 1, 2, 3 and the 2 halves of 3, 2 by 10, 20
+            let mut total = sum(pieces[0].clone(), &[], false);
+            for (w, piece) in [(2., &pieces[1]), (3., &pieces[2]), (10., &halves[0]), (20., &halves[1])] {
+                let w = Rc::new(Node::TensorParam(Tensor::from(w), "w"));
+                total = add(total, mul(sum(piece.clone(), &[], false), w));
+            }
+            total
+        }
+
+        let (_val, grads_map) = grad!(forward, Tensor::ones(&[5]));
+        assert_eq!(grads_map.get("x").unwrap().to_vec(), vec![11., 11., 12., 22., 23.]);
+    }
+
+    #[test]
+    fn test_split_chunk_errors() {
+        use crate::error::RaxError;
+        use crate::ops::{try_chunk, try_split};
+
+        let x = Rc::new(Node::TensorParam(Tensor::ones(&[4, 2]), "x"));
+        assert_eq!(split(x.clone(), 3, -2).len(), 2);
+        assert!(try_split(x.clone(), 2, 2).is_err());
+        assert!(try_split(x.clone(), 2, -3).is_err());
+        assert!(try_chunk(x.clone(), 2, 5).is_err());
+        assert_eq!(try_split(x.clone(), 0, 0).unwrap_err(), RaxError::invalid("split", "split size must be positive"));
+        assert_eq!(try_chunk(x, 0, 0).unwrap_err(), RaxError::invalid("chunk", "number of chunks must be positive"));
+
+        let scalar = Rc::new(Node::TensorParam(Tensor::from(1.), "s"));
+        assert!(try_split(scalar.clone(), 1, 0).is_err());
+        assert!(try_chunk(scalar, 1, -1).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "split size must be positive")]
+    fn test_split_zero_size() {
+        split(Rc::new(Node::TensorParam(Tensor::ones(&[4]), "x")), 0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "number of chunks must be positive")]
+    fn test_chunk_zero_chunks() {
+        chunk(Rc::new(Node::TensorParam(Tensor::ones(&[4]), "x")), 0, 0);
+    }
+
+    #[test]
+    fn test_grads_transcendental() {
+        type UnaryFn = fn(Rc<Node>) -> Rc<Node>;
+        let x: f64 = 0.7;
+        // (op, analytic derivative at x)
+        let cases: [(UnaryFn, f64); 9] = [
+            (exp, x.exp()),
+            (log, 1. / x),
+            (sqrt, 0.5 / x.sqrt()),
+            (rsqrt, -0.5 * x.powf(-1.5)),
+            (|x| pow(x, 3.), 3. * x * x),
+            (abs, 1.),
+            (sin, x.cos()),
+            (cos, -x.sin()),
+            (reciprocal, -1. / (x * x)),
+        ];
+
+        for (op, expected) in cases {
+            let forward = |x: Tensor| op(Rc::new(Node::TensorParam(x, "x")));
+            let (_val, grads_map) = grad!(forward, Tensor::from(x));
+            let g = grads_map.get("x").unwrap().item().unwrap();
+            assert!((g - expected).abs() < 1e-12, "{} != {}", g, expected);
+        }
+
+        let forward = |x: Tensor| abs(Rc::new(Node::TensorParam(x, "x")));
+        let (_val, grads_map) = grad!(forward, Tensor::from(-2.));
+        assert_eq!(grads_map.get("x").unwrap().item().unwrap(), -1.);
+    }
+
+    #[test]
+    fn test_grads_f32() {
+        fn forward(x: Tensor, w: Tensor) -> Rc<Node> {
+            let x = Rc::new(Node::TensorParam(x, "x"));
+            let w = Rc::new(Node::TensorParam(w, "w"));
+            mean(mmul(x, w), &[], false)
+        }
+        let x = Tensor::from_vec(vec![1f32, 2., 3., 4.], &[2, 2]);
+        // an f64 parameter mixed in widens the intermediates, not the f32 gradients
+        let w = Tensor::new(vec![1., 0.5], &[2]);
+
+        let (val, grads_map) = grad!(forward, x, w);
+        assert_eq!(val.dtype(), DType::F64);
+        assert_eq!(grads_map["x"].dtype(), DType::F32);
+        assert_eq!(grads_map["x"].to_vec(), vec![0.5, 0.25, 0.5, 0.25]);
+        assert_eq!(grads_map["w"].dtype(), DType::F64);
+
+        let tree = accum_grads(forward(Tensor::ones(&[2, 2]).to_dtype(DType::F32), Tensor::ones(&[2])).backwards());
+        assert_eq!(tree["x"].dtype(), DType::F32);
+
+        // a 0-d f32 parameter too
+        let s = Rc::new(Node::TensorParam(Tensor::from(2.).to_dtype(DType::F32), "s"));
+        let grads_map = mul(s.clone(), s).grads();
+        assert_eq!(grads_map["s"].dtype(), DType::F32);
+        assert_eq!(grads_map["s"].item().unwrap(), 4.);
+    }
+
+    #[test]
+    fn test_grads_maximum_minimum_ties() {
+        fn forward(a: Tensor, b: Tensor, op: fn(Rc<Node>, Rc<Node>) -> Rc<Node>) -> Rc<Node> {
+            op(Rc::new(Node::TensorParam(a, "a")), Rc::new(Node::TensorParam(b, "b")))
+        }
+        let a = Tensor::from(&[1., 2., 3.] as &[f64]);
+        let b = Tensor::from(&[1., 5., 0.] as &[f64]);
+
+        let (val, grads_map) = grad!(forward, a.clone(), b.clone(), maximum);
+        assert_eq!(val.to_vec(), vec![1., 5., 3.]);
+        assert_eq!(grads_map["a"].to_vec(), vec![0.5, 0., 1.]);
+        assert_eq!(grads_map["b"].to_vec(), vec![0.5, 1., 0.]);
+
+        let (val, grads_map) = grad!(forward, a, b, minimum);
+        assert_eq!(val.to_vec(), vec![1., 2., 0.]);
+        assert_eq!(grads_map["a"].to_vec(), vec![0.5, 1., 0.]);
+        assert_eq!(grads_map["b"].to_vec(), vec![0.5, 0., 1.]);
+
+        // a broadcast argument tied everywhere collects half of each gradient
+        let (_val, grads_map) = grad!(forward, Tensor::ones(&[2, 2]), Tensor::from(&[1.] as &[f64]), maximum);
+        assert_eq!(grads_map["b"].to_vec(), vec![2.]);
+    }
+
+    #[test]
+    fn test_try_graph_fns() {
+        use crate::error::RaxError;
+        use crate::ops::{try_add, try_cat, try_gather, try_mmul, try_reshape, try_softmax, try_sum};
+
+        let a = Rc::new(Node::TensorParam(Tensor::ones(&[2, 3]), "a"));
+        let b = Rc::new(Node::TensorParam(Tensor::ones(&[4]), "b"));
+
+        let err = try_add(a.clone(), b.clone()).unwrap_err();
+        assert_eq!(err, RaxError::shape("add", &[2, 3], &[4]));
+        assert!(try_mmul(a.clone(), b.clone()).is_err());
+        assert!(try_reshape(a.clone(), &[5]).is_err());
+        assert!(try_softmax(a.clone(), 2).is_err());
+        assert!(try_sum(a.clone(), &[0, 3], false).is_err());
+        assert!(try_cat(&[a.clone(), b], 0).is_err());
+        assert!(try_gather(a.clone(), 1, &Tensor::from_vec(vec![5i64, 0], &[2, 1])).is_err());
+
+        // a graph built from `try_` fns differentiates like any other
+        let out = try_sum(try_reshape(a, &[3, 2]).unwrap(), &[], false).unwrap();
+        assert_eq!(out.grads()["a"].to_vec(), vec![1.; 6]);
+    }
+
+    #[test]
+    fn test_grad_nodes_higher_order() {
+        let x = Rc::new(Node::TensorParam(Tensor::from(&[1., 2., -3.] as &[f64]), "x"));
+        let y = sum(pow(x.clone(), 3.), &[], false);
+
+        // d/dx x^3 = 3x^2, d/dx 3x^2 = 6x, d/dx 6x = 6
+        let dx = y.grad_nodes()["x"].clone();
+        assert_eq!(dx.val().to_vec(), vec![3., 12., 27.]);
+        let dxx = sum(dx, &[], false).grad_nodes()["x"].clone();
+        assert_eq!(dxx.val().to_vec(), vec![6., 12., -18.]);
+        let dxxx = sum(dxx, &[], false).grads();
+        assert_eq!(dxxx["x"].to_vec(), vec![6., 6., 6.]);
+
+        // a Hessian-vector product: f = x_0 x_1 x_2, H v with v = (1, 0, 0)
+        // is the gradient of grad(f) . v, (0, x_2, x_1)
+        let f = prod(x.clone(), &[], false);
+        let v = Rc::new(Node::Const(Tensor::from(&[1., 0., 0.] as &[f64])));
+        let hv = sum(mul(f.grad_nodes()["x"].clone(), v), &[], false).grads();
+        assert_eq!(hv["x"].to_vec(), vec![0., -3., 2.]);
+
+        // a gradient penalty, (d l / dw)^2, as a loss of its own
+        let x = Rc::new(Node::TensorParam(Tensor::from(&[1., 2.] as &[f64]), "x"));
+        let w = Rc::new(Node::TensorParam(Tensor::from(2.), "w"));
+        let l = sum(mul(mul(w.clone(), w), x), &[], false);
+        let penalty = sqr(l.grad_nodes()["w"].clone());
+        // (2w sum(x))^2 = 36 w^2 -> 72 w = 144, and 8 w^2 sum(x) = 96 for each x
+        let grads = penalty.grads();
+        assert_eq!(penalty.val().item().unwrap(), 144.);
+        assert_eq!(grads["w"].item().unwrap(), 144.);
+        assert_eq!(grads["x"].to_vec(), vec![96., 96.]);
+    }
 }
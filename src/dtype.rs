@@ -0,0 +1,158 @@
+use std::fmt::Debug;
+
+/// The element type of a tensor. Ordered by promotion: when two dtypes meet in
+/// a binary op the result has the later one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DType {
+    Bool,
+    I64,
+    F32,
+    F64,
+}
+
+impl DType {
+    /// the dtype of a binary op between `self` and `other`
+    pub fn promote(self, other: DType) -> DType {
+        self.max(other)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, DType::F32 | DType::F64)
+    }
+
+    /// bool < int < float. promotion never goes down a category.
+    pub(crate) fn category(self) -> u8 {
+        match self {
+            DType::Bool => 0,
+            DType::I64 => 1,
+            DType::F32 | DType::F64 => 2,
+        }
+    }
+
+    /// what floating point ops (exp, division, ...) produce: floats keep their
+    /// precision, ints and bools become f64
+    pub(crate) fn to_float(self) -> DType {
+        if self.is_float() {
+            self
+        } else {
+            DType::F64
+        }
+    }
+
+    /// what arithmetic produces: adding bools counts them, so gives i64
+    pub(crate) fn to_arith(self) -> DType {
+        if self == DType::Bool {
+            DType::I64
+        } else {
+            self
+        }
+    }
+}
+
+/// A tensor's buffer, holding elements of one `DType`. Ops compute in f64 and
+/// convert on the way in and out, so smaller dtypes save memory, not time.
+/// That includes I64 arithmetic, which is only exact up to 2^53 in magnitude.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Storage {
+    Bool(Vec<bool>),
+    I64(Vec<i64>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+impl Storage {
+    /// `data` converted to `dtype`: floats are rounded to the nearest f32,
+    /// truncated towards zero for i64, and anything nonzero is `true`
+    pub fn from_f64s(data: Vec<f64>, dtype: DType) -> Storage {
+        match dtype {
+            DType::F64 => Storage::F64(data),
+            DType::F32 => Storage::F32(data.into_iter().map(|v| v as f32).collect()),
+            DType::I64 => Storage::I64(data.into_iter().map(|v| v as i64).collect()),
+            DType::Bool => Storage::Bool(data.into_iter().map(|v| v != 0.).collect()),
+        }
+    }
+
+    pub fn dtype(&self) -> DType {
+        match self {
+            Storage::Bool(_) => DType::Bool,
+            Storage::I64(_) => DType::I64,
+            Storage::F32(_) => DType::F32,
+            Storage::F64(_) => DType::F64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Storage::Bool(d) => d.len(),
+            Storage::I64(d) => d.len(),
+            Storage::F32(d) => d.len(),
+            Storage::F64(d) => d.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// element `i` as an f64. bools are 0 or 1.
+    pub fn get(&self, i: usize) -> f64 {
+        match self {
+            Storage::Bool(d) => d[i] as u8 as f64,
+            Storage::I64(d) => d[i] as f64,
+            Storage::F32(d) => d[i] as f64,
+            Storage::F64(d) => d[i],
+        }
+    }
+
+    /// sets element `i`, converting `v` as `from_f64s` does
+    pub fn set(&mut self, i: usize, v: f64) {
+        match self {
+            Storage::Bool(d) => d[i] = v != 0.,
+            Storage::I64(d) => d[i] = v as i64,
+            Storage::F32(d) => d[i] = v as f32,
+            Storage::F64(d) => d[i] = v,
+        }
+    }
+
+    /// the buffer itself, if it's already f64
+    pub(crate) fn as_f64s(&self) -> Option<&[f64]> {
+        match self {
+            Storage::F64(d) => Some(d),
+            _ => None,
+        }
+    }
+}
+
+/// The Rust types a tensor can be built from, see `Tensor::from_vec`.
+pub trait Element: Copy + Debug {
+    const DTYPE: DType;
+    fn into_storage(data: Vec<Self>) -> Storage;
+}
+
+impl Element for bool {
+    const DTYPE: DType = DType::Bool;
+    fn into_storage(data: Vec<Self>) -> Storage {
+        Storage::Bool(data)
+    }
+}
+
+impl Element for i64 {
+    const DTYPE: DType = DType::I64;
+    fn into_storage(data: Vec<Self>) -> Storage {
+        Storage::I64(data)
+    }
+}
+
+impl Element for f32 {
+    const DTYPE: DType = DType::F32;
+    fn into_storage(data: Vec<Self>) -> Storage {
+        Storage::F32(data)
+    }
+}
+
+impl Element for f64 {
+    const DTYPE: DType = DType::F64;
+    fn into_storage(data: Vec<Self>) -> Storage {
+        Storage::F64(data)
+    }
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::dtype::DType;
+
+/// Everything that can go wrong building or differentiating a graph. The
+/// panicking APIs panic with this error's message; their `try_` variants
+/// return it instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaxError {
+    /// `op` can't combine (or produce) these shapes, e.g. adding `[2, 3]` and
+    /// `[4]`, or reshaping `[6]` to `[4]`
+    ShapeMismatch {
+        op: &'static str,
+        lhs: Vec<usize>,
+        rhs: Vec<usize>,
+    },
+    IndexOutOfBounds {
+        index: Vec<usize>,
+        shape: Vec<usize>,
+    },
+    /// `op` was handed a negative entry in an index tensor
+    NegativeIndex { op: &'static str, index: i64 },
+    /// `axis` (possibly negative) doesn't name one of `n_dims` dimensions
+    AxisOutOfRange { axis: isize, n_dims: usize },
+    /// an argument other than a shape or index is out of range for `op`, e.g.
+    /// splitting into pieces of size 0
+    InvalidArgument { op: &'static str, reason: String },
+    /// an optimizer was handed no gradient for a parameter it updates
+    MissingGradient { param: String },
+    DTypeMismatch {
+        op: &'static str,
+        expected: DType,
+        got: DType,
+    },
+    /// a lazily built graph was realized before its placeholder `name` was fed
+    MissingInput { name: String },
+}
+
+impl fmt::Display for RaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaxError::ShapeMismatch { op, lhs, rhs } => {
+                write!(f, "{op}: incompatible shapes {lhs:?} and {rhs:?}")
+            }
+            RaxError::IndexOutOfBounds { index, shape } => {
+                write!(f, "index {index:?} is out of bounds for shape {shape:?}")
+            }
+            RaxError::NegativeIndex { op, index } => write!(f, "{op}: negative index {index}"),
+            RaxError::AxisOutOfRange { axis, n_dims } => {
+                write!(f, "axis {axis} is out of range for {n_dims} dimensions")
+            }
+            RaxError::InvalidArgument { op, reason } => write!(f, "{op}: {reason}"),
+            RaxError::MissingGradient { param } => write!(f, "no gradient for parameter `{param}`"),
+            RaxError::DTypeMismatch { op, expected, got } => {
+                write!(f, "{op}: expected a {expected:?} tensor, got {got:?}")
+            }
+            RaxError::MissingInput { name } => write!(f, "placeholder `{name}` was never fed"),
+        }
+    }
+}
+
+impl Error for RaxError {}
+
+impl RaxError {
+    pub(crate) fn shape(op: &'static str, lhs: &[usize], rhs: &[usize]) -> RaxError {
+        RaxError::ShapeMismatch {
+            op,
+            lhs: lhs.to_vec(),
+            rhs: rhs.to_vec(),
+        }
+    }
+
+    pub(crate) fn invalid(op: &'static str, reason: impl Into<String>) -> RaxError {
+        RaxError::InvalidArgument { op, reason: reason.into() }
+    }
+}
+
+/// unwraps with the error's message, for the panicking counterparts of `try_` fns
+pub(crate) trait OrPanic<T> {
+    fn or_panic(self) -> T;
+}
+
+impl<T> OrPanic<T> for Result<T, RaxError> {
+    #[track_caller]
+    fn or_panic(self) -> T {
+        match self {
+            Ok(v) => v,
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
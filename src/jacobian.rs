@@ -0,0 +1,186 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::backward::GradMap;
+use crate::error::{OrPanic, RaxError};
+use crate::jvp::TangentMap;
+use crate::node::Node;
+use crate::ops::{mul, sum};
+use crate::optimizer::ParamsMap;
+use crate::tensor::Tensor;
+
+/// (name, name) -> the block of second derivatives for that pair of
+/// parameters, see `hessian`
+pub type HessianMap = HashMap<(String, String), Tensor>;
+
+/// The Jacobian of `f`'s output with respect to each parameter in `params`,
+/// keyed by name. For an output of shape `[o..]` and a parameter of shape
+/// `[p..]` the entry has shape `[o.., p..]`, with `[i, j]` = d out_i / d p_j.
+///
+/// Goes with `jacrev` (a backward pass per output element) if the output has
+/// no more elements than the parameters together, `jacfwd` (a forward pass
+/// per parameter element) otherwise.
+pub fn jacobian(f: impl Fn(&ParamsMap) -> Rc<Node>, params: &ParamsMap) -> GradMap {
+    let out = f(params);
+    let n_in: usize = params.0.values().map(Tensor::n_elements).sum();
+    if out.val().n_elements() <= n_in {
+        jacrev_node(&out, params)
+    } else {
+        jacfwd_node(&out, params)
+    }
+}
+
+/// `jacobian` in reverse mode
+pub fn jacrev(f: impl Fn(&ParamsMap) -> Rc<Node>, params: &ParamsMap) -> GradMap {
+    jacrev_node(&f(params), params)
+}
+
+/// `jacobian` in forward mode
+pub fn jacfwd(f: impl Fn(&ParamsMap) -> Rc<Node>, params: &ParamsMap) -> GradMap {
+    jacfwd_node(&f(params), params)
+}
+
+/// The second derivatives of `f`'s (single element) output, one block per pair
+/// of parameters: `[(a, b)]` has shape `[a.., b..]`, with `[i, j]` =
+/// d^2 out / d a_i d b_j. The blocks `(a, b)` and `(b, a)` are transposes.
+///
+/// The gradient graph from `Node::grad_nodes` is differentiated again, a
+/// backward pass per parameter element.
+pub fn hessian(f: impl Fn(&ParamsMap) -> Rc<Node>, params: &ParamsMap) -> HessianMap {
+    try_hessian(f, params).or_panic()
+}
+
+/// errors if `f`'s output has more than one element
+pub fn try_hessian(f: impl Fn(&ParamsMap) -> Rc<Node>, params: &ParamsMap) -> Result<HessianMap, RaxError> {
+    let out = f(params);
+    if out.val().n_elements() != 1 {
+        return Err(RaxError::shape("hessian", out.val().size(), &[]));
+    }
+    let grads = out.grad_nodes();
+
+    let mut map = HessianMap::new();
+    for (a, param) in &params.0 {
+        let rows = match grads.get(a) {
+            Some(g) => jacrev_node(g, params),
+            // `out` doesn't depend on `a`, so neither does its gradient
+            None => GradMap::new(),
+        };
+        for (b, other) in &params.0 {
+            let block = rows.get(b).cloned().unwrap_or_else(|| Tensor::zeros(&cat_shapes(param, other)));
+            map.insert((a.clone(), b.clone()), block);
+        }
+    }
+    Ok(map)
+}
+
+/// row `i` is the gradient of `out_i`
+fn jacrev_node(out: &Rc<Node>, params: &ParamsMap) -> GradMap {
+    let out_val = out.val();
+    let n_out = out_val.n_elements();
+    let mut data: HashMap<&String, Vec<f64>> = params.0.keys().map(|name| (name, vec![])).collect();
+
+    for i in 0..n_out {
+        let picked = sum(mul(out.clone(), Rc::new(Node::Const(one_hot(out_val.size(), i)))), &[], false);
+        let grads = picked.grads();
+        for (name, param) in &params.0 {
+            let row = grads.get(name).map_or(vec![0.; param.n_elements()], Tensor::to_vec);
+            data.get_mut(name).unwrap().extend(row);
+        }
+    }
+
+    data.into_iter()
+        .map(|(name, data)| (name.clone(), Tensor::new(data, &cat_shapes(&out_val, &params.0[name]))))
+        .collect()
+}
+
+/// column `j` of each parameter's entry is the tangent of `out` along that
+/// parameter's element `j`
+fn jacfwd_node(out: &Rc<Node>, params: &ParamsMap) -> GradMap {
+    let out_val = out.val();
+    let n_out = out_val.n_elements();
+
+    params
+        .0
+        .iter()
+        .map(|(name, param)| {
+            let n_in = param.n_elements();
+            let mut data = vec![0.; n_out * n_in];
+            for j in 0..n_in {
+                let tangents = TangentMap::from([(name.clone(), one_hot(param.size(), j))]);
+                for (i, t) in out.jvp(&tangents).to_vec().into_iter().enumerate() {
+                    data[i * n_in + j] = t;
+                }
+            }
+            (name.clone(), Tensor::new(data, &cat_shapes(&out_val, param)))
+        })
+        .collect()
+}
+
+fn one_hot(shape: &[usize], i: usize) -> Tensor {
+    let mut data = vec![0.; shape.iter().product()];
+    data[i] = 1.;
+    Tensor::new(data, shape)
+}
+
+fn cat_shapes(a: &Tensor, b: &Tensor) -> Vec<usize> {
+    [a.size(), b.size()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{add, mmul, pow, sin, tanh};
+    use crate::test_util::{assert_close, param_in, params};
+
+    #[test]
+    fn test_jacobian() {
+        // out = x w + b, so d out_j / d b_k = [j == k] and d out_j / d w_ik = x_i [j == k]
+        let x = Tensor::new(vec![2., -1., 0.5], &[3]);
+        let f = |p: &ParamsMap| add(mmul(Rc::new(Node::Const(x.clone())), param_in(p, "w")), param_in(p, "b"));
+
+        let jac = jacobian(f, &params());
+        assert_eq!(jac["b"].to_vec(), vec![1., 0., 0., 1.]);
+        assert_eq!(jac["w"].size(), &[2, 3, 2]);
+        assert_eq!(jac["w"].at(&vec![1, 0, 1]).unwrap(), 2.);
+        assert_eq!(jac["w"].at(&vec![1, 2, 1]).unwrap(), 0.5);
+        assert_eq!(jac["w"].at(&vec![1, 1, 0]).unwrap(), 0.);
+
+        // forward and reverse mode agree on something nonlinear
+        let f = |p: &ParamsMap| sin(tanh(add(mmul(Rc::new(Node::Const(x.clone())), param_in(p, "w")), param_in(p, "b"))));
+        let (fwd, rev) = (jacfwd(f, &params()), jacrev(f, &params()));
+        for name in ["w", "b"] {
+            assert_close(&fwd[name], &rev[name]);
+        }
+
+        // a parameter the output doesn't depend on gets zeros
+        let jac = jacrev(|p: &ParamsMap| sin(param_in(p, "b")), &params());
+        assert_eq!(jac["w"].size(), &[2, 3, 2]);
+        assert!(jac["w"].to_vec().iter().all(|v| *v == 0.));
+    }
+
+    #[test]
+    fn test_hessian() {
+        // sum(b^3) + sum(x w * b): d^2/db^2 = diag(6b), d^2/dw_ij db_k = x_i [j == k], d^2/dw^2 = 0
+        let x = Tensor::new(vec![2., -1., 0.5], &[3]);
+        let f = |p: &ParamsMap| {
+            let xw = mmul(Rc::new(Node::Const(x.clone())), param_in(p, "w"));
+            add(sum(pow(param_in(p, "b"), 3.), &[], false), sum(mul(xw, param_in(p, "b")), &[], false))
+        };
+        let h = hessian(f, &params());
+
+        assert_eq!(h.len(), 4);
+        let b = params().0["b"].to_vec();
+        let diag: Vec<f64> = (0..4).map(|i| if i % 3 == 0 { 6. * b[i / 3] } else { 0. }).collect();
+        assert_close(&h[&("b".to_string(), "b".to_string())], &Tensor::new(diag, &[2, 2]));
+
+        let wb = &h[&("w".to_string(), "b".to_string())];
+        assert_eq!(wb.size(), &[3, 2, 2]);
+        assert_eq!(wb.at(&vec![0, 1, 1]).unwrap(), 2.);
+        assert_eq!(wb.at(&vec![1, 0, 0]).unwrap(), -1.);
+        assert_eq!(wb.at(&vec![1, 0, 1]).unwrap(), 0.);
+        assert_close(&h[&("b".to_string(), "w".to_string())], &wb.permute(&[2, 0, 1]));
+        assert!(h[&("w".to_string(), "w".to_string())].to_vec().iter().all(|v| *v == 0.));
+
+        let err = try_hessian(|p: &ParamsMap| param_in(p, "b"), &params()).unwrap_err();
+        assert_eq!(err, RaxError::shape("hessian", &[2], &[]));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::backward::topo_sort;
+use crate::error::{OrPanic, RaxError};
+use crate::node::{Node, Placeholder};
+use crate::tensor::Tensor;
+
+/// param name -> tangent, the input of forward mode (`GradMap` is the output of reverse mode)
+pub type TangentMap = HashMap<String, Tensor>;
+
+impl Node {
+    /// Forward-mode differentiation: the derivative of this node in the
+    /// direction `tangents`, i.e. the Jacobian-vector product J t.
+    ///
+    /// Each node's tangent is computed from its arguments' with the op's
+    /// `tangent` rule, in one sweep in evaluation order. There's no backward
+    /// pass, so for few inputs and many outputs this is much cheaper than
+    /// recovering the Jacobian column by column from `grads`. Parameters
+    /// missing from `tangents` are held constant, and subgraphs that only
+    /// depend on those are skipped.
+    pub fn jvp(&self, tangents: &TangentMap) -> Tensor {
+        self.try_jvp(tangents).or_panic()
+    }
+
+    /// errors if a tangent's shape doesn't match its parameter's, or the graph
+    /// needs a placeholder that was never fed (see `lazy`)
+    pub fn try_jvp(&self, tangents: &TangentMap) -> Result<Tensor, RaxError> {
+        propagate(self, |node| match node {
+            Node::TensorParam(_, name) | Node::Placeholder(Placeholder { name, .. }) => tangents.get(*name).cloned(),
+            _ => None,
+        })
+    }
+}
+
+/// Evaluates `f` at `primals`, and differentiates it in the direction
+/// `tangents` (one per primal, of the same shape). Returns the output and its
+/// tangent.
+///
+/// `f` gets the primals as parameter nodes. Any other parameters it creates
+/// are held constant.
+pub fn jvp(f: impl FnOnce(&[Rc<Node>]) -> Rc<Node>, primals: &[Tensor], tangents: &[Tensor]) -> (Tensor, Tensor) {
+    try_jvp(f, primals, tangents).or_panic()
+}
+
+/// errors if there isn't one tangent per primal, of the primal's shape
+pub fn try_jvp(
+    f: impl FnOnce(&[Rc<Node>]) -> Rc<Node>,
+    primals: &[Tensor],
+    tangents: &[Tensor],
+) -> Result<(Tensor, Tensor), RaxError> {
+    if primals.len() != tangents.len() {
+        return Err(RaxError::invalid("jvp", format!("{} primals but {} tangents", primals.len(), tangents.len())));
+    }
+    let inputs: Vec<Rc<Node>> = primals
+        .iter()
+        .map(|p| Rc::new(Node::TensorParam(p.clone(), "primal")))
+        .collect();
+    // the primals are told apart by pointer, as they share a name
+    let seeds: HashMap<*const Node, &Tensor> = std::iter::zip(&inputs, tangents)
+        .map(|(input, t)| (Rc::as_ptr(input), t))
+        .collect();
+
+    let out = f(&inputs);
+    let tangent = propagate(&out, |node| seeds.get(&(node as *const Node)).map(|t| (*t).clone()))?;
+    Ok((out.val(), tangent))
+}
+
+/// Pushes tangents from the nodes `seed` gives one to through the graph.
+/// `None` stands for a zero tangent, and only becomes a tensor of zeros when
+/// an op mixes it with a nonzero one.
+fn propagate(root: &Node, seed: impl Fn(&Node) -> Option<Tensor>) -> Result<Tensor, RaxError> {
+    root.try_realize()?;
+    let mut tangents: HashMap<*const Node, Rc<Tensor>> = HashMap::new();
+
+    for node in topo_sort(root) {
+        let tangent = match node {
+            Node::TensorParam(..) | Node::Placeholder(_) => match seed(node) {
+                Some(tangent) if tangent.size() != node.shape() => {
+                    return Err(RaxError::shape("jvp", node.shape(), tangent.size()))
+                }
+                tangent => tangent.map(Rc::new),
+            },
+            Node::Const(_) => None,
+            Node::BinaryOp(res) => {
+                let (l, r) = (&res.args.0, &res.args.1);
+                match (tangents.get(&Rc::as_ptr(l)), tangents.get(&Rc::as_ptr(r))) {
+                    (None, None) => None,
+                    (tl, tr) => Some(res.op.tangent(
+                        (Rc::new(l.val()), Rc::new(r.val())),
+                        (or_zeros(tl, l), or_zeros(tr, r)),
+                        Rc::new(node.val()),
+                    )),
+                }
+            }
+            Node::UnaryOp(res) => tangents
+                .get(&Rc::as_ptr(&res.arg))
+                .map(|t| res.op.tangent(t.clone(), Rc::new(res.arg.val()), Rc::new(node.val()))),
+            Node::ReduceOp(res) => tangents
+                .get(&Rc::as_ptr(&res.arg))
+                .map(|t| res.op.tangent(t.clone(), Rc::new(res.arg.val()), Rc::new(node.val()))),
+            Node::NaryOp(res) => {
+                let arg_tangents: Vec<Option<&Rc<Tensor>>> =
+                    res.args.iter().map(|arg| tangents.get(&Rc::as_ptr(arg))).collect();
+                if arg_tangents.iter().all(Option::is_none) {
+                    None
+                } else {
+                    let arg_tangents = std::iter::zip(arg_tangents, &res.args).map(|(t, arg)| or_zeros(t, arg)).collect();
+                    let args = res.args.iter().map(|arg| Rc::new(arg.val())).collect();
+                    Some(res.op.tangent(arg_tangents, args, Rc::new(node.val())))
+                }
+            }
+        };
+
+        if let Some(tangent) = tangent {
+            assert_eq!(
+                tangent.size(),
+                node.val().size(),
+                "{} produced a tangent of shape {:?} for an output of shape {:?}",
+                op_name(node),
+                tangent.size(),
+                node.val().size()
+            );
+            tangents.insert(node as *const Node, tangent);
+        }
+    }
+
+    Ok(match tangents.remove(&(root as *const Node)) {
+        Some(tangent) => (*tangent).clone(),
+        None => Tensor::zeros(root.val().size()),
+    })
+}
+
+fn or_zeros(tangent: Option<&Rc<Tensor>>, node: &Node) -> Rc<Tensor> {
+    tangent.cloned().unwrap_or_else(|| Rc::new(Tensor::zeros(node.val().size())))
+}
+
+pub(crate) fn op_name(node: &Node) -> &'static str {
+    match node {
+        Node::BinaryOp(res) => res.op.name(),
+        Node::UnaryOp(res) => res.op.name(),
+        Node::ReduceOp(res) => res.op.name(),
+        Node::NaryOp(res) => res.op.name(),
+        Node::TensorParam(..) => "TensorParam",
+        Node::Const(_) => "Const",
+        Node::Placeholder(_) => "Placeholder",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{add, mmul, mul, sin, sum, tanh};
+
+    #[test]
+    fn test_jvp() {
+        // f(x) = sin(x) * x, f'(x) = cos(x) x + sin(x)
+        let x = [Tensor::from(&[0., 1., 2.] as &[f64])];
+        let t = [Tensor::from(&[1., 1., 0.5] as &[f64])];
+        let (val, tangent) = jvp(|x| mul(sin(x[0].clone()), x[0].clone()), &x, &t);
+
+        let expected: Vec<f64> = std::iter::zip(x[0].to_vec(), t[0].to_vec())
+            .map(|(x, t)| t * (x.cos() * x + x.sin()))
+            .collect();
+        assert_eq!(val.to_vec(), x[0].to_vec().iter().map(|x| x.sin() * x).collect::<Vec<f64>>());
+        for (a, e) in std::iter::zip(tangent.to_vec(), expected) {
+            assert!((a - e).abs() < 1e-12, "{a} vs {e}");
+        }
+    }
+
+    #[test]
+    fn test_jvp_matches_grads() {
+        // for a scalar output, J t is the gradient dotted with t
+        fn forward(w: Rc<Node>, b: Rc<Node>) -> Rc<Node> {
+            let x = Rc::new(Node::Const(Tensor::new(vec![1., -2., 0.5, 3., 1.5, -1.], &[2, 3])));
+            sum(tanh(add(mmul(x, w), b)), &[], false)
+        }
+        let w = Tensor::new(vec![0.1, -0.2, 0.3, 0.4, -0.5, 0.6], &[3, 2]);
+        let b = Tensor::new(vec![0.05, -0.05], &[2]);
+        let (tw, tb) = (Tensor::new(vec![1., 0., -1., 2., 0.5, 0.], &[3, 2]), Tensor::new(vec![0.3, -1.], &[2]));
+
+        let out = forward(Rc::new(Node::TensorParam(w.clone(), "w")), Rc::new(Node::TensorParam(b.clone(), "b")));
+        let grads = out.grads();
+        let dot = |g: &Tensor, t: &Tensor| std::iter::zip(g.to_vec(), t.to_vec()).map(|(g, t)| g * t).sum::<f64>();
+        let expected = dot(&grads["w"], &tw) + dot(&grads["b"], &tb);
+
+        let tangents = TangentMap::from([("w".to_string(), tw.clone()), ("b".to_string(), tb.clone())]);
+        assert!((out.jvp(&tangents).item().unwrap() - expected).abs() < 1e-12);
+
+        let (_, tangent) = jvp(|p| forward(p[0].clone(), p[1].clone()), &[w, b], &[tw, tb]);
+        assert!((tangent.item().unwrap() - expected).abs() < 1e-12);
+
+        // a parameter left out is held constant
+        let only_b = TangentMap::from([("b".to_string(), Tensor::new(vec![0.3, -1.], &[2]))]);
+        assert!((out.jvp(&only_b).item().unwrap() - dot(&grads["b"], &Tensor::new(vec![0.3, -1.], &[2]))).abs() < 1e-12);
+        assert_eq!(out.jvp(&TangentMap::new()).item().unwrap(), 0.);
+    }
+
+    #[test]
+    fn test_jvp_shape_error() {
+        let x = Rc::new(Node::TensorParam(Tensor::ones(&[2, 3]), "x"));
+        let out = sin(x);
+        let tangents = TangentMap::from([("x".to_string(), Tensor::ones(&[3]))]);
+        assert_eq!(out.try_jvp(&tangents).unwrap_err(), RaxError::shape("jvp", &[2, 3], &[3]));
+
+        let err = try_jvp(|x| sin(x[0].clone()), &[Tensor::ones(&[2])], &[]).unwrap_err();
+        assert_eq!(err, RaxError::invalid("jvp", "1 primals but 0 tangents"));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::backward::topo_sort;
+use crate::error::{OrPanic, RaxError};
+use crate::jvp::op_name;
+use crate::node::{Node, Placeholder, Value};
+use crate::tensor::Tensor;
+
+/// placeholder name -> the value to feed it, see `Node::eval`
+pub type Feeds = HashMap<String, Tensor>;
+
+thread_local! {
+    static LAZY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `build` with lazy graph construction: the ops it creates only record
+/// their arguments and output shape, and nothing is computed until the graph
+/// is realized (`Node::realize`, or `Node::val` on any node) or evaluated on
+/// new inputs (`Node::eval`). Shape errors still surface as the ops are built.
+///
+/// Ops over a `placeholder` that hasn't been fed are deferred outside of
+/// `lazy` too.
+pub fn lazy<T>(build: impl FnOnce() -> T) -> T {
+    // puts the previous mode back even if `build` panics
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            LAZY.set(self.0);
+        }
+    }
+    let _reset = Reset(LAZY.replace(true));
+    build()
+}
+
+pub(crate) fn is_lazy() -> bool {
+    LAZY.get()
+}
+
+/// An input of shape `shape`, fed by `name` when the graph is evaluated. It's
+/// differentiated like a `TensorParam` of that name, so parameters can be
+/// placeholders as well and get fed their new values every training step.
+pub fn placeholder(name: &'static str, shape: &[usize]) -> Rc<Node> {
+    Rc::new(Node::Placeholder(Placeholder {
+        name,
+        value: Value::deferred(shape.to_vec()),
+    }))
+}
+
+impl Node {
+    /// Computes every node of the graph that hasn't been yet, in one sweep in
+    /// evaluation order, and returns this node's value. Placeholders keep what
+    /// they were last fed.
+    pub fn realize(&self) -> Tensor {
+        self.try_realize().or_panic()
+    }
+
+    /// errors if the graph needs a placeholder that was never fed
+    pub fn try_realize(&self) -> Result<Tensor, RaxError> {
+        for node in topo_sort(self) {
+            if node.cached().is_none() {
+                compute(node)?;
+            }
+        }
+        Ok(self.cached().expect("realized above"))
+    }
+
+    /// Feeds the placeholders named in `feeds` and evaluates the graph: every
+    /// node that depends on a placeholder is computed again, the rest keep
+    /// their values. So a graph built once (see `lazy`) can be run each
+    /// training step on new inputs, and differentiated with `grads` after.
+    ///
+    /// Names that aren't placeholders of this graph are ignored. Values are
+    /// cached on the nodes, so another graph sharing some of them keeps the
+    /// old ones until it's evaluated itself.
+    pub fn eval(&self, feeds: &Feeds) -> Tensor {
+        self.try_eval(feeds).or_panic()
+    }
+
+    /// errors if a value doesn't have its placeholder's shape, or the graph
+    /// needs a placeholder that was never fed
+    pub fn try_eval(&self, feeds: &Feeds) -> Result<Tensor, RaxError> {
+        let order = topo_sort(self);
+
+        let placeholders: Vec<&Placeholder> = order
+            .iter()
+            .filter_map(|node| match node {
+                Node::Placeholder(p) => Some(p),
+                _ => None,
+            })
+            .collect();
+        for p in &placeholders {
+            if let Some(t) = feeds.get(p.name) {
+                if t.size() != p.value.shape() {
+                    return Err(RaxError::shape("eval", p.value.shape(), t.size()));
+                }
+            }
+        }
+        for p in placeholders {
+            if let Some(t) = feeds.get(p.name) {
+                p.value.set(t.clone());
+            }
+        }
+
+        let mut stale: HashSet<*const Node> = HashSet::new();
+        for node in order {
+            let depends = matches!(node, Node::Placeholder(_))
+                || node.args().iter().any(|arg| stale.contains(&Rc::as_ptr(arg)));
+            if depends {
+                stale.insert(node as *const Node);
+                if let Some(value) = op_value(node) {
+                    value.clear();
+                }
+                if let Node::UnaryOp(res) = node {
+                    res.op.reset();
+                }
+            }
+        }
+        self.try_realize()
+    }
+}
+
+/// computes an op node from its arguments, which have to be computed already
+fn compute(node: &Node) -> Result<(), RaxError> {
+    let arg_val = |arg: &Node| Rc::new(arg.cached().expect("arguments are computed first"));
+    let (value, out) = match node {
+        Node::TensorParam(..) | Node::Const(_) => return Ok(()),
+        Node::Placeholder(p) => return Err(RaxError::MissingInput { name: p.name.to_string() }),
+        Node::BinaryOp(res) => (&res.value, res.op.forward(arg_val(&res.args.0), arg_val(&res.args.1))?),
+        Node::UnaryOp(res) => (&res.value, res.op.forward(arg_val(&res.arg))?),
+        Node::ReduceOp(res) => (&res.value, res.op.forward(arg_val(&res.arg))?),
+        Node::NaryOp(res) => (&res.value, res.op.forward(res.args.iter().map(|arg| arg_val(arg)).collect())?),
+    };
+    assert_eq!(
+        out.size(),
+        value.shape(),
+        "{} computed a value of shape {:?}, but its shape was inferred as {:?}",
+        op_name(node),
+        out.size(),
+        value.shape()
+    );
+    value.set(out);
+    Ok(())
+}
+
+fn op_value(node: &Node) -> Option<&Value> {
+    match node {
+        Node::TensorParam(..) | Node::Const(_) | Node::Placeholder(_) => None,
+        Node::BinaryOp(res) => Some(&res.value),
+        Node::UnaryOp(res) => Some(&res.value),
+        Node::ReduceOp(res) => Some(&res.value),
+        Node::NaryOp(res) => Some(&res.value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::losses::{mse, Reduction};
+    use crate::ops::{add, mmul, relu, sum};
+    use crate::optimizer::{Optimizer, ParamsMap, SGD};
+
+    fn mlp_loss(x: Rc<Node>, y: Rc<Node>, w: Rc<Node>, b: Rc<Node>) -> Rc<Node> {
+        mse(relu(add(mmul(x, w), b)), y, Reduction::Mean)
+    }
+
+    fn batch(step: usize) -> Feeds {
+        let x: Vec<f64> = (0..12).map(|i| ((i + step) as f64 * 0.7).sin()).collect();
+        let y: Vec<f64> = (0..8).map(|i| ((i * step) as f64 * 0.3).cos()).collect();
+        Feeds::from([("x".to_string(), Tensor::new(x, &[4, 3])), ("y".to_string(), Tensor::new(y, &[4, 2]))])
+    }
+
+    #[test]
+    fn test_graph_reuse() {
+        let graph = lazy(|| {
+            let (x, y) = (placeholder("x", &[4, 3]), placeholder("y", &[4, 2]));
+            mlp_loss(x, y, placeholder("w", &[3, 2]), placeholder("b", &[2]))
+        });
+        assert_eq!(graph.shape(), &[] as &[usize]);
+        assert!(graph.cached().is_none());
+
+        let sgd = SGD { lr: 0.1 };
+        let mut params = ParamsMap(HashMap::from([
+            ("w".to_string(), Tensor::new(vec![0.5, -1., 2., 0.25, 1.5, -0.5], &[3, 2])),
+            ("b".to_string(), Tensor::new(vec![0.1, 0.3], &[2])),
+        ]));
+        for step in 0..3 {
+            let feeds: Feeds = batch(step).into_iter().chain(params.0.clone()).collect();
+            let loss = graph.eval(&feeds);
+            let grads = graph.grads();
+
+            // the same step built eagerly
+            let node = |name: &'static str| Rc::new(Node::TensorParam(feeds[name].clone(), name));
+            let eager = mlp_loss(node("x"), node("y"), node("w"), node("b"));
+            assert_eq!(loss.to_vec(), eager.val().to_vec());
+            let eager_grads = eager.grads();
+            for name in ["x", "w", "b"] {
+                assert_eq!(grads[name].to_vec(), eager_grads[name].to_vec());
+            }
+
+            // the data's gradients are ignored, as only `params` are updated
+            params = sgd.update(params, grads);
+        }
+    }
+
+    #[test]
+    fn test_eval_resets_saved_state() {
+        let x = placeholder("x", &[3]);
+        let out = sum(relu(x), &[], false);
+
+        // Relu's mask saved by `forward` follows each feed
+        let feeds = Feeds::from([("x".to_string(), Tensor::new(vec![1., -2., 3.], &[3]))]);
+        assert_eq!(out.eval(&feeds).to_vec(), vec![4.]);
+        assert_eq!(out.grads()["x"].to_vec(), vec![1., 0., 1.]);
+        let feeds = Feeds::from([("x".to_string(), Tensor::new(vec![-1., 2., -3.], &[3]))]);
+        assert_eq!(out.eval(&feeds).to_vec(), vec![2.]);
+        assert_eq!(out.grads()["x"].to_vec(), vec![0., 1., 0.]);
+    }
+
+    #[test]
+    fn test_lazy_errors() {
+        let x = placeholder("x", &[2, 3]);
+        // outside of `lazy` too, as `x` has no value yet
+        let out = sum(x, &[1], false);
+        assert_eq!(out.shape(), &[2]);
+        assert_eq!(out.try_realize().unwrap_err(), RaxError::MissingInput { name: "x".to_string() });
+
+        let feeds = Feeds::from([("x".to_string(), Tensor::ones(&[3, 2]))]);
+        assert_eq!(out.try_eval(&feeds).unwrap_err(), RaxError::shape("eval", &[2, 3], &[3, 2]));
+        let feeds = Feeds::from([("x".to_string(), Tensor::ones(&[2, 3]))]);
+        assert_eq!(out.eval(&feeds).to_vec(), vec![3., 3.]);
+
+        // shape errors show up as the graph is built
+        let err = lazy(|| crate::ops::try_mmul(placeholder("a", &[2, 3]), placeholder("b", &[2, 3]))).unwrap_err();
+        assert_eq!(err, RaxError::shape("matmul", &[2, 3], &[2, 3]));
+
+        // a parameter-only graph is computed on first use
+        let w = Rc::new(Node::TensorParam(Tensor::ones(&[2, 2]), "w"));
+        let out = lazy(|| add(w.clone(), w));
+        assert!(!is_lazy());
+        assert!(out.cached().is_none());
+        assert_eq!(out.val().to_vec(), vec![2.; 4]);
+    }
+}
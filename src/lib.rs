@@ -1,5 +1,15 @@
 pub mod backward;
+pub mod dtype;
+pub mod error;
+pub mod jacobian;
+pub mod jvp;
+pub mod lazy;
+pub mod losses;
 pub mod node;
 pub mod ops;
 pub mod optimizer;
-pub mod tensor;
\ No newline at end of file
+pub mod random;
+pub mod tensor;
+#[cfg(test)]
+mod test_util;
+pub mod vmap;
\ No newline at end of file
@@ -0,0 +1,170 @@
+use std::rc::Rc;
+
+use crate::node::Node;
+use crate::ops::{
+    abs, add, add_scalar, gather, log, log_softmax, mean, mul, mul_scalar, neg, relu, softplus, sqr, squeeze, sub,
+    sum, xlogx,
+};
+use crate::tensor::Tensor;
+
+/// how the per-element losses are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    #[default]
+    Mean,
+    Sum,
+    /// keep the per-element losses
+    None,
+}
+
+fn reduce(loss: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    match reduction {
+        Reduction::Mean => mean(loss, &[], false),
+        Reduction::Sum => sum(loss, &[], false),
+        Reduction::None => loss,
+    }
+}
+
+/// 1 - x
+fn one_minus(x: Rc<Node>) -> Rc<Node> {
+    add_scalar(neg(x), 1.)
+}
+
+/// squared error, (pred - target)^2
+pub fn mse(pred: Rc<Node>, target: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    reduce(sqr(sub(pred, target)), reduction)
+}
+
+/// absolute error, |pred - target|
+pub fn l1(pred: Rc<Node>, target: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    reduce(abs(sub(pred, target)), reduction)
+}
+
+/// squared error for |pred - target| <= delta, linear beyond it:
+/// 0.5 d^2 if |d| <= delta, else delta (|d| - 0.5 delta)
+pub fn huber(pred: Rc<Node>, target: Rc<Node>, delta: f64, reduction: Reduction) -> Rc<Node> {
+    let a = abs(sub(pred, target));
+    // the part of |d| beyond delta, and what's left of it within delta
+    let excess = relu(add_scalar(a.clone(), -delta));
+    let within = sub(a, excess.clone());
+    let loss = add(mul_scalar(sqr(within), 0.5), mul_scalar(excess, delta));
+    reduce(loss, reduction)
+}
+
+/// -(t ln(p) + (1 - t) ln(1 - p)) for probabilities `pred` in (0, 1).
+/// prefer `binary_cross_entropy_with_logits`, which can't hit ln(0).
+pub fn binary_cross_entropy(pred: Rc<Node>, target: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    let pos = mul(target.clone(), log(pred.clone()));
+    let neg_ = mul(one_minus(target), log(one_minus(pred)));
+    reduce(neg(add(pos, neg_)), reduction)
+}
+
+/// `binary_cross_entropy(sigmoid(logits), target)`, computed as
+/// softplus(x) - t x so it stays finite for large logits
+pub fn binary_cross_entropy_with_logits(logits: Rc<Node>, target: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    reduce(sub(softplus(logits.clone()), mul(target, logits)), reduction)
+}
+
+/// negative log likelihood of the `target` classes. `log_probs` is `[C]` or
+/// `[N, C, ...]` with classes along dim 1, and `target` holds I64 class
+/// indices with the class dimension removed (a scalar for `[C]`).
+pub fn nll(log_probs: Rc<Node>, target: &Tensor, reduction: Reduction) -> Rc<Node> {
+    let class_dim = if log_probs.shape().len() == 1 { 0 } else { 1 };
+    let picked = gather(log_probs, class_dim, &target.unsqueeze(class_dim));
+    reduce(neg(squeeze(picked, class_dim)), reduction)
+}
+
+/// `nll(log_softmax(logits))`, with the same shapes as `nll`
+pub fn cross_entropy(logits: Rc<Node>, target: &Tensor, reduction: Reduction) -> Rc<Node> {
+    let class_dim = if logits.shape().len() == 1 { 0 } else { 1 };
+    nll(log_softmax(logits, class_dim), target, reduction)
+}
+
+/// KL(target || input), where `input` holds log-probabilities and `target`
+/// probabilities: t (ln(t) - input), with 0 where t is 0. like pytorch,
+/// `Reduction::Mean` averages over every element, not the batch.
+pub fn kl_div(input: Rc<Node>, target: Rc<Node>, reduction: Reduction) -> Rc<Node> {
+    reduce(sub(xlogx(target.clone()), mul(target, input)), reduction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::sigmoid;
+    use crate::test_util::param;
+
+    fn close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in std::iter::zip(a, b) {
+            assert!((a - b).abs() < 1e-5, "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn test_regression_losses() {
+        let pred = || param(Tensor::from(&[1., 2., 5.] as &[f64]), "pred");
+        let target = || param(Tensor::from(&[1.5, 2., 1.] as &[f64]), "target");
+
+        close(&mse(pred(), target(), Reduction::None).val().to_vec(), &[0.25, 0., 16.]);
+        close(&mse(pred(), target(), Reduction::Sum).val().to_vec(), &[16.25]);
+        close(&l1(pred(), target(), Reduction::Mean).val().to_vec(), &[1.5]);
+        close(&huber(pred(), target(), 1., Reduction::None).val().to_vec(), &[0.125, 0., 3.5]);
+
+        let g = mse(pred(), target(), Reduction::Mean).grads();
+        close(&g["pred"].to_vec(), &[-1. / 3., 0., 8. / 3.]);
+        let g = huber(pred(), target(), 1., Reduction::Sum).grads();
+        close(&g["pred"].to_vec(), &[-0.5, 0., 1.]);
+    }
+
+    #[test]
+    fn test_binary_cross_entropy() {
+        let logits = Tensor::from(&[-2., 0.5, 3.] as &[f64]);
+        let target = || param(Tensor::from(&[0., 1., 1.] as &[f64]), "target");
+
+        let from_probs = binary_cross_entropy(sigmoid(param(logits.clone(), "x")), target(), Reduction::None);
+        let from_logits = binary_cross_entropy_with_logits(param(logits.clone(), "x"), target(), Reduction::None);
+        close(&from_probs.val().to_vec(), &from_logits.val().to_vec());
+        close(&from_logits.val().to_vec(), &[0.126928, 0.474077, 0.048587]);
+
+        // d/dx = sigmoid(x) - t
+        let g = binary_cross_entropy_with_logits(param(logits.clone(), "x"), target(), Reduction::Sum).grads();
+        close(&g["x"].to_vec(), &[0.119203, -0.377541, -0.047426]);
+
+        let huge = param(Tensor::from(&[-1000., 1000.] as &[f64]), "x");
+        let target = param(Tensor::from(&[1., 0.] as &[f64]), "target");
+        let loss = binary_cross_entropy_with_logits(huge, target, Reduction::None);
+        close(&loss.val().to_vec(), &[1000., 1000.]);
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let logits = Tensor::new(vec![1., 2., 3., 1000., 0., -1000.], &[2, 3]);
+        let target = Tensor::from_vec(vec![2i64, 0], &[2]);
+
+        let loss = cross_entropy(param(logits.clone(), "x"), &target, Reduction::None);
+        assert_eq!(loss.shape(), &[2]);
+        close(&loss.val().to_vec(), &[0.407606, 0.]);
+
+        // d/dx = softmax(x) - onehot(target), averaged over the batch
+        let g = cross_entropy(param(logits, "x"), &target, Reduction::Mean).grads();
+        close(&g["x"].to_vec(), &[0.045015, 0.122364, -0.167379, 0., 0., 0.]);
+
+        let logits = Tensor::new(vec![1., 2., 3., 0., 0., 0.], &[2, 3]);
+        let loss = cross_entropy(param(logits, "x"), &target, Reduction::None);
+        close(&loss.val().to_vec(), &[0.407606, 3f64.ln()]);
+
+        // a single unbatched example
+        let loss = cross_entropy(param(Tensor::from(&[1., 2., 3.] as &[f64]), "x"), &Tensor::from_vec(vec![2i64], &[]), Reduction::Sum);
+        close(&loss.val().to_vec(), &[0.407606]);
+    }
+
+    #[test]
+    fn test_kl_div() {
+        let log_q = Tensor::log(&Tensor::from(&[0.25, 0.25, 0.5] as &[f64]));
+        let p = Tensor::from(&[0.5, 0.5, 0.] as &[f64]);
+
+        let loss = kl_div(param(log_q, "q"), param(p, "p"), Reduction::Sum);
+        close(&loss.val().to_vec(), &[2f64.ln()]);
+        close(&loss.grads()["q"].to_vec(), &[-0.5, -0.5, 0.]);
+    }
+}
@@ -1,60 +1,196 @@
+use std::cell::RefCell;
 use std::fmt::Debug;
 use std::rc::Rc;
 
-use crate::tensor::Tensor;
+use crate::error::{OrPanic, RaxError};
+use crate::lazy::is_lazy;
+use crate::tensor::{broadcast_shapes, Tensor};
 
 #[derive(Debug)]
 pub enum Node {
     BinaryOp(BinaryOpResult),
     UnaryOp(UnaryOpResult),
     ReduceOp(ReduceOpResult),
+    NaryOp(NaryOpResult),
     TensorParam(Tensor, &'static str), // (Tensor, name) // this is gross but works for now
+    /// a value that isn't differentiated, e.g. a mask in a gradient graph
+    Const(Tensor),
+    /// an input fed by name when the graph is evaluated, see `lazy::placeholder`
+    Placeholder(Placeholder),
+}
+
+/// An op's output. Computed when the node is built, or left for `Node::realize`
+/// if the node was built lazily (see `lazy`); the shape is known either way.
+#[derive(Debug)]
+pub struct Value {
+    shape: Vec<usize>,
+    tensor: RefCell<Option<Tensor>>,
+}
+
+impl Value {
+    pub(crate) fn new(tensor: Tensor) -> Value {
+        Value {
+            shape: tensor.size().to_vec(),
+            tensor: RefCell::new(Some(tensor)),
+        }
+    }
+
+    pub(crate) fn deferred(shape: Vec<usize>) -> Value {
+        Value {
+            shape,
+            tensor: RefCell::new(None),
+        }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// `None` until realized
+    pub fn get(&self) -> Option<Tensor> {
+        self.tensor.borrow().clone()
+    }
+
+    /// for a node whose value has to be there already, e.g. after `val` on a
+    /// later node realized it
+    pub(crate) fn realized(&self) -> Tensor {
+        self.get().expect("node used before it was realized")
+    }
+
+    pub(crate) fn set(&self, tensor: Tensor) {
+        *self.tensor.borrow_mut() = Some(tensor);
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.tensor.borrow_mut() = None;
+    }
+}
+
+#[derive(Debug)]
+pub struct Placeholder {
+    pub name: &'static str,
+    pub value: Value,
 }
 
 #[derive(Debug)]
 pub struct BinaryOpResult {
     pub op: Box<dyn BinaryOp>,
     pub args: (Rc<Node>, Rc<Node>),
-    pub value: Tensor,
+    pub value: Value,
 }
 
 #[derive(Debug)]
 pub struct UnaryOpResult {
     pub op: Box<dyn UnaryOp>,
     pub arg: Rc<Node>,
-    pub value: Tensor,
+    pub value: Value,
 }
 
 #[derive(Debug)]
 pub struct ReduceOpResult {
     pub op: Box<dyn ReduceOp>,
     pub arg: Rc<Node>,
-    pub value: Tensor,
+    pub value: Value,
+}
+
+/// An op over any number of arguments, e.g. concatenation.
+#[derive(Debug)]
+pub struct NaryOpResult {
+    pub op: Box<dyn NaryOp>,
+    pub args: Vec<Rc<Node>>,
+    pub value: Value,
 }
 
 pub trait BinaryOp: Debug {
     fn name(&self) -> &'static str;
     fn get_grads(&self, upstream: Rc<Tensor>, args: (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>);
-    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Tensor;
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError>;
+    /// the shape `forward` would give, without computing it (see `lazy`).
+    /// errors where `forward` would. elementwise ops broadcast.
+    fn shape(&self, left: &[usize], right: &[usize]) -> Result<Vec<usize>, RaxError> {
+        broadcast_shapes(left, right).ok_or_else(|| RaxError::shape(self.name(), left, right))
+    }
+    /// `get_grads` built out of nodes, so that the gradients can themselves be
+    /// differentiated (see `Node::grad_nodes`). `out` is the node of this op.
+    fn grad_graph(&self, upstream: Rc<Node>, args: (Rc<Node>, Rc<Node>), out: Rc<Node>) -> (Rc<Node>, Rc<Node>);
+    /// the forward-mode rule: the output's tangent given the arguments' (see
+    /// `Node::jvp`). an argument that isn't being differentiated gets zeros.
+    fn tangent(&self, args: (Rc<Tensor>, Rc<Tensor>), tangents: (Rc<Tensor>, Rc<Tensor>), out: Rc<Tensor>) -> Rc<Tensor>;
+    /// the batching rule (see `vmap`): this op over a batch, where the
+    /// arguments flagged in `batched` have a batch axis in front. `out` is the
+    /// output of one example, and the result is `out` with the batch axis in front.
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node>;
 }
 
 pub trait UnaryOp: Debug {
-    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor>;
+    /// computes the output from `arg`. anything else the backward pass needs
+    /// (e.g. a mask) can be saved on `self` here, behind a `RefCell` since the
+    /// node is shared. a lazily built node runs `forward` again on every
+    /// `Node::eval`, after `reset`.
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError>;
+    /// drops whatever `forward` saved, when `Node::eval` clears the output
+    fn reset(&self) {}
+    /// see `BinaryOp::shape`. elementwise ops keep the shape.
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(arg.to_vec())
+    }
+    /// `arg` and `out` are the input and output saved from `forward`
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor>;
+    /// see `BinaryOp::grad_graph`
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, out: Rc<Node>) -> Rc<Node>;
+    /// see `BinaryOp::tangent`
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor>;
+    /// see `BinaryOp::batch`. `arg` is always batched.
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node>;
     fn name(&self) -> &'static str;
 }
 
 pub trait ReduceOp: Debug {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError>;
+    /// see `BinaryOp::shape`
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError>;
     fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor>;
+    /// see `BinaryOp::grad_graph`
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, out: Rc<Node>) -> Rc<Node>;
+    /// see `BinaryOp::tangent`
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor>;
+    /// see `BinaryOp::batch`. `arg` is always batched.
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node>;
     fn name(&self) -> &'static str;
 }
 
+pub trait NaryOp: Debug {
+    fn name(&self) -> &'static str;
+    /// one gradient per argument, in argument order
+    fn get_grads(&self, upstream: Rc<Tensor>, args: Vec<Rc<Tensor>>) -> Vec<Rc<Tensor>>;
+    fn forward(&self, args: Vec<Rc<Tensor>>) -> Result<Tensor, RaxError>;
+    /// see `BinaryOp::shape`
+    fn shape(&self, args: &[&[usize]]) -> Result<Vec<usize>, RaxError>;
+    /// see `BinaryOp::grad_graph`
+    fn grad_graph(&self, upstream: Rc<Node>, args: Vec<Rc<Node>>, out: Rc<Node>) -> Vec<Rc<Node>>;
+    /// see `BinaryOp::tangent`
+    fn tangent(&self, tangents: Vec<Rc<Tensor>>, args: Vec<Rc<Tensor>>, out: Rc<Tensor>) -> Rc<Tensor>;
+    /// see `BinaryOp::batch`
+    fn batch(&self, args: Vec<Rc<Node>>, batched: Vec<bool>, out: &Tensor) -> Rc<Node>;
+}
+
 impl Node {
-    pub fn new_unr_res(op: impl UnaryOp + 'static, arg: Rc<Node>, value: Tensor) -> Rc<Node> {
-        Rc::new(Node::UnaryOp(UnaryOpResult {
+    /// panics if `op` can't be applied to `arg`, see `try_new_unr_res`
+    pub fn new_unr_res(op: impl UnaryOp + 'static, arg: Rc<Node>) -> Rc<Node> {
+        Node::try_new_unr_res(op, arg).or_panic()
+    }
+
+    pub fn try_new_unr_res(op: impl UnaryOp + 'static, arg: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+        let value = if defer(&[&arg]) {
+            Value::deferred(op.shape(arg.shape())?)
+        } else {
+            Value::new(op.forward(Rc::new(arg.val()))?)
+        };
+        Ok(Rc::new(Node::UnaryOp(UnaryOpResult {
             op: Box::new(op),
             arg,
             value,
-        }))
+        })))
     }
 
     pub fn new_bin_res(
@@ -62,27 +198,104 @@ impl Node {
         l: Rc<Node>,
         r: Rc<Node>, 
     ) -> Rc<Node> {
-        Rc::new(Node::BinaryOp(BinaryOpResult {
-            args: (l.clone(), r.clone()),
-            value: op.forward(Rc::new(l.val()), Rc::new(r.val())),
+        Node::try_new_bin_res(op, l, r).or_panic()
+    }
+
+    pub fn try_new_bin_res(op: impl BinaryOp + 'static, l: Rc<Node>, r: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+        let value = if defer(&[&l, &r]) {
+            Value::deferred(op.shape(l.shape(), r.shape())?)
+        } else {
+            Value::new(op.forward(Rc::new(l.val()), Rc::new(r.val()))?)
+        };
+        Ok(Rc::new(Node::BinaryOp(BinaryOpResult {
+            args: (l, r),
+            value,
             op: Box::new(op),
-        }))
+        })))
     }
 
-    pub fn new_red_res(op: impl ReduceOp + 'static, arg: Rc<Node>, value: Tensor) -> Rc<Node> {
-        Rc::new(Node::ReduceOp(ReduceOpResult {
+    pub fn new_red_res(op: impl ReduceOp + 'static, arg: Rc<Node>) -> Rc<Node> {
+        Node::try_new_red_res(op, arg).or_panic()
+    }
+
+    pub fn try_new_red_res(op: impl ReduceOp + 'static, arg: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+        let value = if defer(&[&arg]) {
+            Value::deferred(op.shape(arg.shape())?)
+        } else {
+            Value::new(op.forward(Rc::new(arg.val()))?)
+        };
+        Ok(Rc::new(Node::ReduceOp(ReduceOpResult {
             op: Box::new(op),
             arg,
             value,
-        }))
+        })))
+    }
+
+    pub fn new_nary_res(op: impl NaryOp + 'static, args: Vec<Rc<Node>>) -> Rc<Node> {
+        Node::try_new_nary_res(op, args).or_panic()
     }
 
+    pub fn try_new_nary_res(op: impl NaryOp + 'static, args: Vec<Rc<Node>>) -> Result<Rc<Node>, RaxError> {
+        let value = if defer(&args.iter().collect::<Vec<_>>()) {
+            let shapes: Vec<&[usize]> = args.iter().map(|arg| arg.shape()).collect();
+            Value::deferred(op.shape(&shapes)?)
+        } else {
+            Value::new(op.forward(args.iter().map(|arg| Rc::new(arg.val())).collect())?)
+        };
+        Ok(Rc::new(Node::NaryOp(NaryOpResult {
+            value,
+            op: Box::new(op),
+            args,
+        })))
+    }
+
+    /// The nodes this node was computed from, in argument order.
+    pub fn args(&self) -> Vec<&Rc<Node>> {
+        match self {
+            Node::TensorParam(..) | Node::Const(..) | Node::Placeholder(_) => vec![],
+            Node::BinaryOp(res) => vec![&res.args.0, &res.args.1],
+            Node::UnaryOp(res) => vec![&res.arg],
+            Node::ReduceOp(res) => vec![&res.arg],
+            Node::NaryOp(res) => res.args.iter().collect(),
+        }
+    }
+
+    /// The value of this node. Cheap, as the returned tensor shares its buffer.
+    /// A lazily built node is realized first, see `Node::realize`.
     pub fn val(&self) -> Tensor {
+        match self.cached() {
+            Some(tensor) => tensor,
+            None => self.realize(),
+        }
+    }
+
+    /// The shape of `val`, known without realizing the node.
+    pub fn shape(&self) -> &[usize] {
         match self {
-            Node::TensorParam(tensor, _) => tensor.clone(),
-            Node::BinaryOp(res) => res.value.clone(),
-            Node::UnaryOp(res) => res.value.clone(),
-            Node::ReduceOp(res) => res.value.clone(),
+            Node::TensorParam(tensor, _) | Node::Const(tensor) => tensor.size(),
+            Node::Placeholder(p) => p.value.shape(),
+            Node::BinaryOp(res) => res.value.shape(),
+            Node::UnaryOp(res) => res.value.shape(),
+            Node::ReduceOp(res) => res.value.shape(),
+            Node::NaryOp(res) => res.value.shape(),
         }
     }
+
+    /// The value if it's been computed (or fed, for a placeholder).
+    pub(crate) fn cached(&self) -> Option<Tensor> {
+        match self {
+            Node::TensorParam(tensor, _) | Node::Const(tensor) => Some(tensor.clone()),
+            Node::Placeholder(p) => p.value.get(),
+            Node::BinaryOp(res) => res.value.get(),
+            Node::UnaryOp(res) => res.value.get(),
+            Node::ReduceOp(res) => res.value.get(),
+            Node::NaryOp(res) => res.value.get(),
+        }
+    }
+}
+
+/// an op is computed when it's built, unless in a `lazy` block or over a node
+/// that hasn't been computed itself (e.g. a placeholder that hasn't been fed)
+fn defer(args: &[&Rc<Node>]) -> bool {
+    is_lazy() || args.iter().any(|arg| arg.cached().is_none())
 }
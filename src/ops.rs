@@ -1,22 +1,95 @@
+use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::node::{BinaryOp, Node, ReduceOp, UnaryOp};
-use crate::tensor::Tensor;
+use crate::error::{OrPanic, RaxError};
+use crate::node::{BinaryOp, NaryOp, Node, ReduceOp, UnaryOp};
+use crate::tensor::{
+    self, broadcast_shapes, elementwise_broadcasted_map, elementwise_map, gelu_inner, try_normalize_axis, SliceRange, Tensor,
+};
 
 #[derive(Debug)]
 pub struct MMulOp;
 impl BinaryOp for MMulOp {
+    /// works on the promoted (at least 2-D) forms of the arguments: the last two
+    /// axes are transposed, broadcast batch dimensions are summed out, and any
+    /// dimension added to promote a 1-D argument is removed again.
     fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
-        let l_grad = Tensor::mmul(&upstream, &r.transpose(0, 1));
-        let r_grad = Tensor::mmul(&l.transpose(0, 1), &upstream);
+        let (l_p, r_p) = (l.promote_matmul_lhs(), r.promote_matmul_rhs());
+
+        // put back the dimensions that `matmul` squeezed out of the result
+        let mut upstream = (*upstream).clone();
+        if r.size().len() == 1 {
+            upstream.unsqueeze_(upstream.size().len());
+        }
+        if l.size().len() == 1 {
+            upstream.unsqueeze_(upstream.size().len() - 1);
+        }
+
+        let mut l_grad = upstream
+            .batched_matmul(&r_p.transpose(-1, -2))
+            .unbroadcast(l_p.size())
+            .unwrap();
+        let mut r_grad = l_p
+            .transpose(-1, -2)
+            .batched_matmul(&upstream)
+            .unbroadcast(r_p.size())
+            .unwrap();
+
+        if l.size().len() == 1 {
+            l_grad.squeeze_(0);
+        }
+        if r.size().len() == 1 {
+            r_grad.squeeze_(1);
+        }
         (Rc::new(l_grad), Rc::new(r_grad))
     }
 
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let (l_dims, r_dims) = (l.shape().to_vec(), r.shape().to_vec());
+        let l_p = if l_dims.len() == 1 { unsqueeze(l, 0) } else { l };
+        let r_p = if r_dims.len() == 1 { unsqueeze(r, -1) } else { r };
+
+        // put back the dimensions that `matmul` squeezed out of the result
+        let mut upstream = upstream;
+        if r_dims.len() == 1 {
+            upstream = unsqueeze(upstream, -1);
+        }
+        if l_dims.len() == 1 {
+            upstream = unsqueeze(upstream, -2);
+        }
+
+        let l_grad = sum_to(mmul(upstream.clone(), transpose(r_p.clone(), -1, -2)), l_p.shape());
+        let r_grad = sum_to(mmul(transpose(l_p, -1, -2), upstream), r_p.shape());
+        (reshape(l_grad, &l_dims), reshape(r_grad, &r_dims))
+    }
+
+    /// (l + tl)(r + tr) = lr + tl r + l tr + ...
+    fn tangent(&self, (l, r): (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::add(&Tensor::mmul(&tl, &r), &Tensor::mmul(&l, &tr)).unwrap())
+    }
+
+    /// 1-D arguments are made into matrices first, as `matmul` would, so that
+    /// the batch axis can't be mistaken for one of the matrix axes
+    fn batch(&self, (l, r): (Rc<Node>, Rc<Node>), (l_batched, r_batched): (bool, bool), out: &Tensor) -> Rc<Node> {
+        let n_batch = batch_size(if l_batched { &l } else { &r });
+        let l_rank = |l: &Rc<Node>| l.shape().len() - l_batched as usize;
+        let r_rank = |r: &Rc<Node>| r.shape().len() - r_batched as usize;
+        let l = if l_rank(&l) == 1 { unsqueeze(l, -2) } else { l };
+        let r = if r_rank(&r) == 1 { unsqueeze(r, -1) } else { r };
+        let rank = l_rank(&l).max(r_rank(&r));
+        let l = if l_batched { with_rank(l, rank) } else { l };
+        let r = if r_batched { with_rank(r, rank) } else { r };
+        reshape(mmul(l, r), &[&[n_batch], out.size()].concat())
+    }
+
     fn name(&self) -> &'static str {
         "MatMul"
     }
-    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Tensor {
-        Tensor::mmul(&left, &right)
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::try_mmul(&left, &right)
+    }
+    fn shape(&self, left: &[usize], right: &[usize]) -> Result<Vec<usize>, RaxError> {
+        matmul_shape(left, right)
     }
 }
 
@@ -26,11 +99,22 @@ impl BinaryOp for AddOp {
     fn get_grads(&self, upstream: Rc<Tensor>, _args: (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
         (upstream.clone(), upstream.clone())
     }
+    fn grad_graph(&self, upstream: Rc<Node>, _args: (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        (upstream.clone(), upstream)
+    }
+    fn tangent(&self, _args: (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::add(&tl, &tr).unwrap())
+    }
+    /// the batched argument is lined up with the output's axes, and the other broadcasts
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        add(l, r)
+    }
     fn name(&self) -> &'static str {
         "Add"
     }
-    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Tensor {
-        Tensor::add(&left, &right).unwrap()
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::add(&left, &right)
     }
 }
 
@@ -43,11 +127,21 @@ impl BinaryOp for SubOp {
             Rc::new(Tensor::mul(&upstream.clone(), &Tensor::from(-1.)).unwrap()),
         )
     }
+    fn grad_graph(&self, upstream: Rc<Node>, _args: (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        (upstream.clone(), neg(upstream))
+    }
+    fn tangent(&self, _args: (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::sub(&tl, &tr).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        sub(l, r)
+    }
     fn name(&self) -> &'static str {
         "Sub"
     }
-    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Tensor {
-        Tensor::sub(&left, &right).unwrap()
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::sub(&left, &right)
     }
 }
 
@@ -56,25 +150,239 @@ impl BinaryOp for SubOp {
 pub struct MulOp;
 impl BinaryOp for MulOp {
     fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
-        let asdf = (
+        (
             Rc::new(Tensor::mul(&r.clone(), &upstream.clone()).unwrap()),
             Rc::new(Tensor::mul(&l.clone(), &upstream.clone()).unwrap()),
-        );
-        asdf
+        )
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        (mul(upstream.clone(), r), mul(upstream, l))
+    }
+    fn tangent(&self, (l, r): (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::add(&Tensor::mul(&tl, &r).unwrap(), &Tensor::mul(&l, &tr).unwrap()).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        mul(l, r)
     }
     fn name(&self) -> &'static str {
         "Mul"
     }
-    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Tensor {
-        Tensor::mul(&left, &right).unwrap()
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::mul(&left, &right)
+    }
+}
+
+#[derive(Debug)]
+pub struct DivOp;
+impl BinaryOp for DivOp {
+    /// d/dl l/r = 1/r, d/dr l/r = -l/r^2
+    fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        let r_grad = elementwise_broadcasted_map("div", &l, &r, &|l, r| -l / (r * r)).unwrap();
+        (
+            Rc::new(Tensor::div(&upstream, &r).unwrap()),
+            Rc::new(Tensor::mul(&upstream, &r_grad).unwrap()),
+        )
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let l_grad = div(upstream, r.clone());
+        (l_grad.clone(), neg(mul(l_grad, div(l, r))))
+    }
+    /// (tl - out tr) / r
+    fn tangent(&self, (_l, r): (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), out: Rc<Tensor>) -> Rc<Tensor> {
+        let num = Tensor::sub(&tl, &Tensor::mul(&out, &tr).unwrap()).unwrap();
+        Rc::new(Tensor::div(&num, &r).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        div(l, r)
+    }
+    fn name(&self) -> &'static str {
+        "Div"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::div(&left, &right)
+    }
+}
+
+/// how much of the upstream gradient goes to `l` for elementwise max/min: all
+/// of it where `l` wins, none where `r` wins, and half each on a tie
+fn extremum_share(l: &Tensor, r: &Tensor, l_wins: &impl Fn(f64, f64) -> bool) -> Tensor {
+    elementwise_broadcasted_map("extremum", l, r, &|l, r| {
+        if l == r {
+            0.5
+        } else if l_wins(l, r) {
+            1.
+        } else {
+            0.
+        }
+    })
+    .unwrap()
+}
+
+/// gives the upstream gradient to whichever argument was the maximum.
+/// ties split it evenly, like pytorch.
+#[derive(Debug)]
+pub struct MaxOp;
+impl BinaryOp for MaxOp {
+    fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        let l_share = extremum_share(&l, &r, &|l, r| l > r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        (chain(&upstream, &l_share), chain(&upstream, &r_share))
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let l_share = extremum_share(&l.val(), &r.val(), &|l, r| l > r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        (mul(upstream.clone(), constant(l_share)), mul(upstream, constant(r_share)))
+    }
+    fn tangent(&self, (l, r): (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        let l_share = extremum_share(&l, &r, &|l, r| l > r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        Rc::new(Tensor::add(&chain(&tl, &l_share), &chain(&tr, &r_share)).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        maximum(l, r)
+    }
+    fn name(&self) -> &'static str {
+        "Max"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::maximum(&left, &right)
+    }
+}
+
+/// see `MaxOp`
+#[derive(Debug)]
+pub struct MinOp;
+impl BinaryOp for MinOp {
+    fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        let l_share = extremum_share(&l, &r, &|l, r| l < r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        (chain(&upstream, &l_share), chain(&upstream, &r_share))
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let l_share = extremum_share(&l.val(), &r.val(), &|l, r| l < r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        (mul(upstream.clone(), constant(l_share)), mul(upstream, constant(r_share)))
+    }
+    fn tangent(&self, (l, r): (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        let l_share = extremum_share(&l, &r, &|l, r| l < r);
+        let r_share = elementwise_map(&l_share, &|s| 1. - s).unwrap();
+        Rc::new(Tensor::add(&chain(&tl, &l_share), &chain(&tr, &r_share)).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        minimum(l, r)
+    }
+    fn name(&self) -> &'static str {
+        "Min"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::minimum(&left, &right)
+    }
+}
+
+/// picks `l` where `cond` is nonzero and `r` elsewhere, see `Tensor::where_`
+#[derive(Debug)]
+pub struct WhereOp {
+    cond: Tensor,
+}
+impl BinaryOp for WhereOp {
+    /// each branch gets the upstream gradient where it was selected, 0 elsewhere
+    fn get_grads(&self, upstream: Rc<Tensor>, _args: (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        let zero = Tensor::from(0.);
+        (
+            Rc::new(Tensor::where_(&self.cond, &upstream, &zero).unwrap()),
+            Rc::new(Tensor::where_(&self.cond, &zero, &upstream).unwrap()),
+        )
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, _args: (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let zero = || constant(Tensor::from(0.));
+        (where_(&self.cond, upstream.clone(), zero()), where_(&self.cond, zero(), upstream))
+    }
+    fn tangent(&self, _args: (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::where_(&self.cond, &tl, &tr).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        where_(&self.cond, l, r)
+    }
+    fn name(&self) -> &'static str {
+        "Where"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::where_(&self.cond, &left, &right)
+    }
+    fn shape(&self, left: &[usize], right: &[usize]) -> Result<Vec<usize>, RaxError> {
+        let cond = self.cond.size();
+        let shape = broadcast_shapes(cond, left).ok_or_else(|| RaxError::shape("where", cond, left))?;
+        broadcast_shapes(&shape, right).ok_or_else(|| RaxError::shape("where", &shape, right))
+    }
+}
+
+/// `l^r` with a tensor exponent, see `PowScalarOp` for a constant one
+#[derive(Debug)]
+pub struct PowOp;
+impl BinaryOp for PowOp {
+    /// d/dl l^r = r l^(r-1), d/dr l^r = ln(l) l^r. the latter is taken as 0
+    /// where l is 0, which is its limit for r > 0
+    fn get_grads(&self, upstream: Rc<Tensor>, (l, r): (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        let l_grad = elementwise_broadcasted_map("pow", &l, &r, &|l, r| r * l.powf(r - 1.)).unwrap();
+        let r_grad =
+            elementwise_broadcasted_map("pow", &l, &r, &|l, r| if l == 0. { 0. } else { l.ln() * l.powf(r) }).unwrap();
+        (chain(&upstream, &l_grad), chain(&upstream, &r_grad))
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, (l, r): (Rc<Node>, Rc<Node>), out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        let l_grad = mul(upstream.clone(), mul(r.clone(), pow_tensor(l.clone(), add_scalar(r, -1.))));
+        // ln(l) l^r, with l = 0 swapped for 1 so the log (and its gradient) stays finite
+        let l_is_zero = Tensor::eq(&l.val(), &Tensor::from(0.)).unwrap();
+        let safe_l = where_(&l_is_zero, constant(Tensor::from(1.)), l);
+        let r_grad = mul(upstream, mul(log(safe_l), out));
+        (l_grad, r_grad)
+    }
+    /// elementwise, so each half of the backward rule scales its own tangent
+    fn tangent(&self, args: (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        let (dl, _) = self.get_grads(tl, args.clone());
+        let (_, dr) = self.get_grads(tr, args);
+        Rc::new(Tensor::add(&dl, &dr).unwrap())
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> Rc<Node> {
+        let (l, r) = align_elementwise(args, batched, out);
+        pow_tensor(l, r)
+    }
+    fn name(&self) -> &'static str {
+        "Pow"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::pow_tensor(&left, &right)
     }
 }
 
 #[derive(Debug)]
 pub struct SqrOp;
 impl UnaryOp for SqrOp {
-    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>) -> Rc<Tensor> {
-        Rc::new(Tensor::mul(&Tensor::from(2.), &upstream).unwrap())
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::sqr(&arg)
+    }
+
+    /// d/dx x^2 = 2x
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::mul(&arg, &Tensor::from(2.)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, mul_scalar(arg, 2.))
+    }
+
+    /// elementwise, so the tangent is scaled by the same local derivative as the gradient
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    /// elementwise, so the batch axis is just one more axis
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        sqr(arg)
     }
 
     fn name(&self) -> &'static str {
@@ -83,85 +391,2051 @@ impl UnaryOp for SqrOp {
 }
 
 #[derive(Debug)]
-pub struct MeanOp {
-    input_n_elements: usize,
+pub struct NegOp;
+impl UnaryOp for NegOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::mul(&arg, &Tensor::from(-1.))
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::mul(&upstream, &Tensor::from(-1.)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        neg(upstream)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        neg(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Neg"
+    }
 }
-impl ReduceOp for MeanOp {
+
+/// upstream * local derivative, elementwise
+fn chain(upstream: &Tensor, local: &Tensor) -> Rc<Tensor> {
+    Rc::new(Tensor::mul(upstream, local).unwrap())
+}
+
+#[derive(Debug)]
+pub struct ExpOp;
+impl UnaryOp for ExpOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::exp(&arg))
+    }
+
+    /// d/dx e^x = e^x, which is just the output
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &out)
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, out)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        exp(arg)
+    }
+
     fn name(&self) -> &'static str {
-        "Mean"
+        "Exp"
     }
-    /// gradient of mean is 1/n
-    /// where n is the number of elements in the input tensor
-    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
-        let upstream2  = Tensor::div(&upstream, &Tensor::from(self.input_n_elements as f64)).unwrap();
-        let out = Tensor::mul(&upstream2, &Tensor::ones(arg.size())).unwrap();
-        Rc::new(out)
+}
+
+#[derive(Debug)]
+pub struct LogOp;
+impl UnaryOp for LogOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::log(&arg))
+    }
+
+    /// d/dx ln(x) = 1/x
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::div(&upstream, &arg).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        div(upstream, arg)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        log(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Log"
     }
 }
 
 #[derive(Debug)]
-pub struct ReluOp {
-    // which elements in the input were greater than 0.
-    // used to zero out the upstream gradient.
-    input_gt_zero_mask: Tensor,
+pub struct SqrtOp;
+impl UnaryOp for SqrtOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::sqrt(&arg))
+    }
+
+    /// d/dx sqrt(x) = 1 / (2 sqrt(x))
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let two_out = Tensor::mul(&out, &Tensor::from(2.)).unwrap();
+        Rc::new(Tensor::div(&upstream, &two_out).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        div(upstream, mul_scalar(out, 2.))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        sqrt(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Sqrt"
+    }
 }
-impl UnaryOp for ReluOp {
-    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>) -> Rc<Tensor> {
-        Rc::new(Tensor::mul(&self.input_gt_zero_mask.clone(), &upstream.clone()).unwrap())
+
+#[derive(Debug)]
+pub struct RsqrtOp;
+impl UnaryOp for RsqrtOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::rsqrt(&arg))
+    }
+
+    /// d/dx x^(-1/2) = -1/2 x^(-3/2) = -1/2 out^3
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::mul(&Tensor::pow(&out, 3.), &Tensor::from(-0.5)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, mul_scalar(pow(out, 3.), -0.5))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        rsqrt(arg)
     }
 
     fn name(&self) -> &'static str {
-        "Relu"
+        "Rsqrt"
     }
 }
 
-macro_rules! create_binary_op {
-    ($name:ident, $op:ident) => {
-        pub fn $name(l: Rc<Node>, r: Rc<Node>) -> Rc<Node> {
-            Node::new_bin_res($op, l, r)
-        }
-    };
+/// `x^p` for a constant scalar `p`
+#[derive(Debug)]
+pub struct PowScalarOp {
+    p: f64,
 }
+impl UnaryOp for PowScalarOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::pow(&arg, self.p))
+    }
+
+    /// d/dx x^p = p x^(p-1)
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        let local = Tensor::mul(&Tensor::pow(&arg, self.p - 1.), &Tensor::from(self.p)).unwrap();
+        chain(&upstream, &local)
+    }
 
-create_binary_op!(add, AddOp);
-create_binary_op!(mul, MulOp);
-create_binary_op!(mmul, MMulOp);
-create_binary_op!(sub, SubOp);
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, mul_scalar(pow(arg, self.p - 1.), self.p))
+    }
 
-// UNARY
-//
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
 
-pub fn sqr(x: Rc<Node>) -> Rc<Node> {
-    Node::new_unr_res(SqrOp, x.clone(), Tensor::mul(&x.val(), &x.val()).unwrap())
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        pow(arg, self.p)
+    }
+
+    fn name(&self) -> &'static str {
+        "PowScalar"
+    }
 }
 
-pub fn neg(x: Rc<Node>) -> Rc<Node> {
-    Node::new_unr_res(
-        SqrOp,
-        x.clone(),
-        Tensor::mul(&x.val(), &Tensor::from(-1.)).unwrap(),
-    )
+/// `c * x` for a constant scalar `c`
+#[derive(Debug)]
+pub struct MulScalarOp {
+    c: f64,
 }
+impl UnaryOp for MulScalarOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::mul(&arg, &Tensor::from(self.c))
+    }
 
-pub fn relu(x: Rc<Node>) -> Rc<Node> {
-    Node::new_unr_res(
-        ReluOp {
-            input_gt_zero_mask: Tensor::gt(&x.val(), 0.),
-        },
-        x.clone(),
-        Tensor::relu(&x.val()),
-    )
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::mul(&upstream, &Tensor::from(self.c)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul_scalar(upstream, self.c)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        mul_scalar(arg, self.c)
+    }
+
+    fn name(&self) -> &'static str {
+        "MulScalar"
+    }
 }
 
-// REDUCE
-// 
+/// `x + c` for a constant scalar `c`
+#[derive(Debug)]
+pub struct AddScalarOp {
+    c: f64,
+}
+impl UnaryOp for AddScalarOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Tensor::add(&arg, &Tensor::from(self.c))
+    }
 
-pub fn mean(x: Rc<Node>) -> Rc<Node> {
-    Node::new_red_res(
-        MeanOp {
-            input_n_elements: x.val().n_elements(),
-        },
-        x.clone(),
-        Tensor::mean(&x.val()),
-    )
-}
\ No newline at end of file
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        upstream
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        upstream
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        add_scalar(arg, self.c)
+    }
+
+    fn name(&self) -> &'static str {
+        "AddScalar"
+    }
+}
+
+/// `x ln(x)`, taken to be 0 at 0 (its limit) rather than NaN
+#[derive(Debug)]
+pub struct XLogXOp;
+impl UnaryOp for XLogXOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        elementwise_map(&arg, &|x| if x == 0. { 0. } else { x * x.ln() })
+    }
+
+    /// d/dx x ln(x) = ln(x) + 1
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &elementwise_map(&arg, &|x| x.ln() + 1.).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, add_scalar(log(arg), 1.))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        xlogx(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "XLogX"
+    }
+}
+
+#[derive(Debug)]
+pub struct AbsOp;
+impl UnaryOp for AbsOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::abs(&arg))
+    }
+
+    /// d/dx |x| = sign(x), taking 0 at 0
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::sign(&arg))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, constant(Tensor::sign(&arg.val())))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        abs(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Abs"
+    }
+}
+
+#[derive(Debug)]
+pub struct SinOp;
+impl UnaryOp for SinOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::sin(&arg))
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::cos(&arg))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, cos(arg))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        sin(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Sin"
+    }
+}
+
+#[derive(Debug)]
+pub struct CosOp;
+impl UnaryOp for CosOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::cos(&arg))
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::mul(&Tensor::sin(&arg), &Tensor::from(-1.)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, neg(sin(arg)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        cos(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Cos"
+    }
+}
+
+#[derive(Debug)]
+pub struct ReciprocalOp;
+impl UnaryOp for ReciprocalOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::reciprocal(&arg))
+    }
+
+    /// d/dx 1/x = -1/x^2 = -out^2
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::mul(&Tensor::sqr(&out).unwrap(), &Tensor::from(-1.)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, neg(sqr(out)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        reciprocal(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Reciprocal"
+    }
+}
+
+#[derive(Debug)]
+pub struct SumOp {
+    axes: Vec<isize>,
+    keepdim: bool,
+}
+impl ReduceOp for SumOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        self.shape(arg.size())?;
+        Ok(arg.sum(&self.axes, self.keepdim))
+    }
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        reduced_shape(arg, &self.axes, self.keepdim)
+    }
+    fn name(&self) -> &'static str {
+        "Sum"
+    }
+    /// every input element contributes once to its slice's sum,
+    /// so the upstream gradient is just broadcast back over the reduced axes
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
+        let upstream = upstream.unsqueeze_reduced(&self.axes, arg.size().len());
+        Rc::new(Tensor::mul(&upstream, &Tensor::ones(arg.size())).unwrap())
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        expand_reduced(upstream, &self.axes, &arg)
+    }
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.sum(&self.axes, false).reshape(out.size()))
+    }
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        batch_reduce(sum, &self.axes, arg, out)
+    }
+}
+
+#[derive(Debug)]
+pub struct MeanOp {
+    axes: Vec<isize>,
+    keepdim: bool,
+}
+impl ReduceOp for MeanOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        self.shape(arg.size())?;
+        Ok(arg.mean(&self.axes, self.keepdim))
+    }
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        reduced_shape(arg, &self.axes, self.keepdim)
+    }
+    fn name(&self) -> &'static str {
+        "Mean"
+    }
+    /// gradient of mean is 1/n
+    /// where n is the number of elements in each reduced slice
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
+        let n = Tensor::from(arg.n_reduced(&self.axes) as f64);
+        let upstream = upstream.unsqueeze_reduced(&self.axes, arg.size().len());
+        let upstream2 = Tensor::div(&upstream, &n).unwrap();
+        let out = Tensor::mul(&upstream2, &Tensor::ones(arg.size())).unwrap();
+        Rc::new(out)
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let n = arg.val().n_reduced(&self.axes) as f64;
+        mul_scalar(expand_reduced(upstream, &self.axes, &arg), 1. / n)
+    }
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.mean(&self.axes, false).reshape(out.size()))
+    }
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        batch_reduce(mean, &self.axes, arg, out)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReduceMaxOp {
+    axes: Vec<isize>,
+    keepdim: bool,
+}
+impl ReduceOp for ReduceMaxOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        self.shape(arg.size())?;
+        Ok(arg.max(&self.axes, self.keepdim))
+    }
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        reduced_shape(arg, &self.axes, self.keepdim)
+    }
+    fn name(&self) -> &'static str {
+        "ReduceMax"
+    }
+    /// the gradient is routed entirely to the (first) maximal element of each slice
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
+        let upstream = upstream.unsqueeze_reduced(&self.axes, arg.size().len());
+        Rc::new(Tensor::mul(&arg.argmax_mask(&self.axes), &upstream).unwrap())
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let mask = arg.val().argmax_mask(&self.axes);
+        mul(expand_reduced(upstream, &self.axes, &arg), constant(mask))
+    }
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let picked = Tensor::mul(&arg.argmax_mask(&self.axes), &tangent).unwrap();
+        Rc::new(picked.sum(&self.axes, false).reshape(out.size()))
+    }
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        batch_reduce(max, &self.axes, arg, out)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReduceMinOp {
+    axes: Vec<isize>,
+    keepdim: bool,
+}
+impl ReduceOp for ReduceMinOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        self.shape(arg.size())?;
+        Ok(arg.min(&self.axes, self.keepdim))
+    }
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        reduced_shape(arg, &self.axes, self.keepdim)
+    }
+    fn name(&self) -> &'static str {
+        "ReduceMin"
+    }
+    /// the gradient is routed entirely to the (first) minimal element of each slice
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
+        let upstream = upstream.unsqueeze_reduced(&self.axes, arg.size().len());
+        Rc::new(Tensor::mul(&arg.argmin_mask(&self.axes), &upstream).unwrap())
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let mask = arg.val().argmin_mask(&self.axes);
+        mul(expand_reduced(upstream, &self.axes, &arg), constant(mask))
+    }
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let picked = Tensor::mul(&arg.argmin_mask(&self.axes), &tangent).unwrap();
+        Rc::new(picked.sum(&self.axes, false).reshape(out.size()))
+    }
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        batch_reduce(min, &self.axes, arg, out)
+    }
+}
+
+#[derive(Debug)]
+pub struct ProdOp {
+    axes: Vec<isize>,
+    keepdim: bool,
+}
+impl ReduceOp for ProdOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        self.shape(arg.size())?;
+        Ok(arg.prod(&self.axes, self.keepdim))
+    }
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        reduced_shape(arg, &self.axes, self.keepdim)
+    }
+    fn name(&self) -> &'static str {
+        "Prod"
+    }
+    /// ∂/∂x_i prod(x) = product of every other element in the slice
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>) -> Rc<Tensor> {
+        let upstream = upstream.unsqueeze_reduced(&self.axes, arg.size().len());
+        Rc::new(Tensor::mul(&arg.prod_others(&self.axes), &upstream).unwrap())
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        let upstream = expand_reduced(upstream, &self.axes, &arg);
+        let has_zero = arg.val().to_vec().contains(&0.);
+        if has_zero {
+            // out / x would divide by zero. the product of the others is then
+            // taken as a constant, so is only right to first order
+            return mul(upstream, constant(arg.val().prod_others(&self.axes)));
+        }
+        mul(upstream, div(expand_reduced(out, &self.axes, &arg), arg))
+    }
+    /// sum over the slice of t_i * the product of every other element
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let terms = Tensor::mul(&arg.prod_others(&self.axes), &tangent).unwrap();
+        Rc::new(terms.sum(&self.axes, false).reshape(out.size()))
+    }
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        batch_reduce(prod, &self.axes, arg, out)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReluOp {
+    // which elements in the input were greater than 0, saved by `forward`.
+    // used to zero out the upstream gradient.
+    input_gt_zero_mask: RefCell<Option<Tensor>>,
+}
+impl ReluOp {
+    fn mask(&self) -> Tensor {
+        self.input_gt_zero_mask.borrow().clone().expect("Relu backward before forward")
+    }
+}
+impl UnaryOp for ReluOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        *self.input_gt_zero_mask.borrow_mut() = Some(Tensor::gt(&arg, &Tensor::from(0.))?);
+        Ok(Tensor::relu(&arg))
+    }
+
+    fn reset(&self) {
+        *self.input_gt_zero_mask.borrow_mut() = None;
+    }
+
+    /// the upstream gradient where the input was greater than 0, zeros elsewhere
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::mul(&self.mask(), &upstream).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, constant(self.mask()))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        relu(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Relu"
+    }
+}
+
+#[derive(Debug)]
+pub struct SigmoidOp;
+impl UnaryOp for SigmoidOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::sigmoid(&arg))
+    }
+
+    /// d/dx σ(x) = σ(x) (1 - σ(x))
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &elementwise_map(&out, &|s| s * (1. - s)).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, mul(out.clone(), one_minus(out)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        sigmoid(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Sigmoid"
+    }
+}
+
+#[derive(Debug)]
+pub struct TanhOp;
+impl UnaryOp for TanhOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::tanh(&arg))
+    }
+
+    /// d/dx tanh(x) = 1 - tanh(x)^2
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &elementwise_map(&out, &|t| 1. - t * t).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, one_minus(sqr(out)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        tanh(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Tanh"
+    }
+}
+
+#[derive(Debug)]
+pub struct GeluOp;
+impl UnaryOp for GeluOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::gelu(&arg))
+    }
+
+    /// derivative of the tanh approximation, with u = sqrt(2/π) (x + 0.044715 x^3):
+    /// 0.5 (1 + tanh(u)) + 0.5 x (1 - tanh(u)^2) du/dx
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        let local = elementwise_map(&arg, &|x| {
+            let t = gelu_inner(x).tanh();
+            let du = (2. / std::f64::consts::PI).sqrt() * (1. + 3. * 0.044715 * x * x);
+            0.5 * (1. + t) + 0.5 * x * (1. - t * t) * du
+        })
+        .unwrap();
+        chain(&upstream, &local)
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let c = (2. / std::f64::consts::PI).sqrt();
+        let t = tanh(mul_scalar(add(arg.clone(), mul_scalar(pow(arg.clone(), 3.), 0.044715)), c));
+        let du = mul_scalar(add_scalar(mul_scalar(sqr(arg.clone()), 3. * 0.044715), 1.), c);
+        let local = add(
+            mul_scalar(add_scalar(t.clone(), 1.), 0.5),
+            mul(mul_scalar(arg, 0.5), mul(one_minus(sqr(t)), du)),
+        );
+        mul(upstream, local)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        gelu(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Gelu"
+    }
+}
+
+#[derive(Debug)]
+pub struct SiluOp;
+impl UnaryOp for SiluOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::silu(&arg))
+    }
+
+    /// d/dx x σ(x) = σ(x) (1 + x (1 - σ(x)))
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        let local = elementwise_map(&arg, &|x| {
+            let s = tensor::sigmoid(x);
+            s * (1. + x * (1. - s))
+        })
+        .unwrap();
+        chain(&upstream, &local)
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let s = sigmoid(arg.clone());
+        mul(upstream, mul(s.clone(), add_scalar(mul(arg, one_minus(s)), 1.)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        silu(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Silu"
+    }
+}
+
+#[derive(Debug)]
+pub struct LeakyReluOp {
+    slope: f64,
+}
+impl UnaryOp for LeakyReluOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::leaky_relu(&arg, self.slope))
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        let slope = self.slope;
+        chain(&upstream, &elementwise_map(&arg, &|x| if x > 0. { 1. } else { slope }).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let slope = self.slope;
+        let local = elementwise_map(&arg.val(), &|x| if x > 0. { 1. } else { slope }).unwrap();
+        mul(upstream, constant(local))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        leaky_relu(arg, self.slope)
+    }
+
+    fn name(&self) -> &'static str {
+        "LeakyRelu"
+    }
+}
+
+#[derive(Debug)]
+pub struct EluOp {
+    alpha: f64,
+}
+impl UnaryOp for EluOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::elu(&arg, self.alpha))
+    }
+
+    /// 1 where x > 0, otherwise alpha e^x = out + alpha. branches on `arg`, as
+    /// the sign of `out` only matches it for alpha >= 0
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let alpha = self.alpha;
+        let positive = Tensor::gt(&arg, &Tensor::from(0.)).unwrap();
+        let below = elementwise_map(&out, &|o| o + alpha).unwrap();
+        chain(&upstream, &Tensor::where_(&positive, &Tensor::from(1.), &below).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        let positive = Tensor::gt(&arg.val(), &Tensor::from(0.)).unwrap();
+        mul(upstream, where_(&positive, constant(Tensor::from(1.)), add_scalar(out, self.alpha)))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        elu(arg, self.alpha)
+    }
+
+    fn name(&self) -> &'static str {
+        "Elu"
+    }
+}
+
+#[derive(Debug)]
+pub struct SoftplusOp;
+impl UnaryOp for SoftplusOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::softplus(&arg))
+    }
+
+    /// d/dx ln(1 + e^x) = σ(x)
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        chain(&upstream, &Tensor::sigmoid(&arg))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        mul(upstream, sigmoid(arg))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        softplus(arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "Softplus"
+    }
+}
+
+#[derive(Debug)]
+pub struct SoftmaxOp {
+    dim: isize,
+}
+impl UnaryOp for SoftmaxOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_idx(self.dim)?;
+        Ok(arg.softmax(self.dim))
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        try_normalize_axis(self.dim, arg.len())?;
+        Ok(arg.to_vec())
+    }
+
+    /// the Jacobian is diag(y) - y yᵀ, so the vector-Jacobian product is
+    /// y * (g - sum(g * y))
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let dot = Tensor::mul(&upstream, &out).unwrap().sum(&[self.dim], true);
+        chain(&out, &Tensor::sub(&upstream, &dot).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        let dot = sum(mul(upstream.clone(), out.clone()), &[self.dim], true);
+        mul(out, sub(upstream, dot))
+    }
+
+    /// the Jacobian is symmetric, so this is the backward rule again
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        self.get_grads(tangent, arg, out)
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        softmax(arg, batch_dim(self.dim))
+    }
+
+    fn name(&self) -> &'static str {
+        "Softmax"
+    }
+}
+
+#[derive(Debug)]
+pub struct LogSoftmaxOp {
+    dim: isize,
+}
+impl UnaryOp for LogSoftmaxOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_idx(self.dim)?;
+        Ok(arg.log_softmax(self.dim))
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        try_normalize_axis(self.dim, arg.len())?;
+        Ok(arg.to_vec())
+    }
+
+    /// g - softmax(x) * sum(g), where softmax(x) = e^out
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let total = upstream.sum(&[self.dim], true);
+        let softmax = Tensor::exp(&out);
+        Rc::new(Tensor::sub(&upstream, &Tensor::mul(&softmax, &total).unwrap()).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        let total = sum(upstream.clone(), &[self.dim], true);
+        sub(upstream, mul(exp(out), total))
+    }
+
+    /// t - sum(softmax(x) * t)
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let dot = Tensor::mul(&Tensor::exp(&out), &tangent).unwrap().sum(&[self.dim], true);
+        Rc::new(Tensor::sub(&tangent, &dot).unwrap())
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        log_softmax(arg, batch_dim(self.dim))
+    }
+
+    fn name(&self) -> &'static str {
+        "LogSoftmax"
+    }
+}
+
+#[derive(Debug)]
+pub struct LogSumExpOp {
+    dim: isize,
+}
+impl UnaryOp for LogSumExpOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_idx(self.dim)?;
+        Ok(arg.logsumexp(self.dim, false))
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        let mut shape = arg.to_vec();
+        shape.remove(try_normalize_axis(self.dim, arg.len())?);
+        Ok(shape)
+    }
+
+    /// the gradient of logsumexp is softmax(x) = e^(x - out)
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let softmax = Tensor::exp(&Tensor::sub(&arg, &out.unsqueeze(self.dim)).unwrap());
+        chain(&softmax, &upstream.unsqueeze(self.dim))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, out: Rc<Node>) -> Rc<Node> {
+        let softmax = exp(sub(arg, unsqueeze(out, self.dim)));
+        mul(softmax, unsqueeze(upstream, self.dim))
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        let softmax = Tensor::exp(&Tensor::sub(&arg, &out.unsqueeze(self.dim)).unwrap());
+        Rc::new(Tensor::mul(&softmax, &tangent).unwrap().sum(&[self.dim], false))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        logsumexp(arg, batch_dim(self.dim))
+    }
+
+    fn name(&self) -> &'static str {
+        "LogSumExp"
+    }
+}
+
+#[derive(Debug)]
+pub struct ReshapeOp {
+    shape: Vec<usize>,
+}
+impl UnaryOp for ReshapeOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_reshape(&self.shape)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        if arg.iter().product::<usize>() != self.shape.iter().product() {
+            return Err(RaxError::shape("reshape", arg, &self.shape));
+        }
+        Ok(self.shape.clone())
+    }
+
+    /// reshaping doesn't touch the elements, so the gradient just needs the
+    /// input's shape back. covers `view`, `flatten`, `squeeze` and `unsqueeze` too.
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.reshape(arg.size()))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        reshape(upstream, arg.shape())
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.reshape(out.size()))
+    }
+
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        reshape(arg.clone(), &batch_shape(&arg, out.size()))
+    }
+
+    fn name(&self) -> &'static str {
+        "Reshape"
+    }
+}
+
+#[derive(Debug)]
+pub struct ExpandOp {
+    shape: Vec<usize>,
+}
+impl UnaryOp for ExpandOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_expand(&self.shape)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(probe(arg).try_expand(&self.shape)?.size().to_vec())
+    }
+
+    /// each input element was copied to every position along the expanded
+    /// dimensions, so their gradients are summed back
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.unbroadcast(arg.size()).unwrap())
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        sum_to(upstream, arg.shape())
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.expand(&self.shape))
+    }
+
+    /// the example's axes are lined up with the trailing ones of `shape` first
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let shape = batch_shape(&arg, &self.shape);
+        expand(with_rank(arg, self.shape.len()), &shape)
+    }
+
+    fn name(&self) -> &'static str {
+        "Expand"
+    }
+}
+
+#[derive(Debug)]
+pub struct PermuteOp {
+    dims: Vec<isize>,
+}
+impl PermuteOp {
+    fn inverse(&self) -> Vec<isize> {
+        let n_dims = self.dims.len();
+        let mut inverse = vec![0; n_dims];
+        for (i, d) in self.dims.iter().enumerate() {
+            inverse[d.rem_euclid(n_dims as isize) as usize] = i as isize;
+        }
+        inverse
+    }
+}
+impl UnaryOp for PermuteOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_permute(&self.dims)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(probe(arg).try_permute(&self.dims)?.size().to_vec())
+    }
+
+    /// permute the gradient back with the inverse permutation
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.permute(&self.inverse()))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        permute(upstream, &self.inverse())
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.permute(&self.dims))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let n_dims = self.dims.len() as isize;
+        let dims: Vec<isize> = std::iter::once(0).chain(self.dims.iter().map(|d| d.rem_euclid(n_dims) + 1)).collect();
+        permute(arg, &dims)
+    }
+
+    fn name(&self) -> &'static str {
+        "Permute"
+    }
+}
+
+#[derive(Debug)]
+pub struct SliceOp {
+    ranges: Vec<SliceRange>,
+}
+impl UnaryOp for SliceOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_slice(&self.ranges)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(probe(arg).try_slice(&self.ranges)?.size().to_vec())
+    }
+
+    /// the upstream gradient goes back into the sliced region, zeros elsewhere
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(arg.size()).slice_scatter(&self.ranges, &upstream))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let shape = arg.shape().to_vec();
+        Node::new_unr_res(SliceScatterOp { ranges: self.ranges.clone(), shape }, upstream)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.slice(&self.ranges))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        slice(arg, &batch_ranges(&self.ranges))
+    }
+
+    fn name(&self) -> &'static str {
+        "Slice"
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexSelectOp {
+    dim: isize,
+    indices: Vec<usize>,
+}
+impl UnaryOp for IndexSelectOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_index_select(self.dim, &self.indices)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        let mut shape = arg.to_vec();
+        shape[try_normalize_axis(self.dim, arg.len())?] = self.indices.len();
+        Ok(shape)
+    }
+
+    /// each selected entry's gradient is added back to where it came from
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(arg.size()).index_add(self.dim, &self.indices, &upstream))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let op = IndexAddOp {
+            dim: self.dim,
+            indices: self.indices.clone(),
+            shape: arg.shape().to_vec(),
+        };
+        Node::new_unr_res(op, upstream)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.index_select(self.dim, &self.indices))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        index_select(arg, batch_dim(self.dim), &self.indices)
+    }
+
+    fn name(&self) -> &'static str {
+        "IndexSelect"
+    }
+}
+
+#[derive(Debug)]
+pub struct GatherOp {
+    dim: isize,
+    index: Tensor,
+}
+impl UnaryOp for GatherOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_gather(self.dim, &self.index)
+    }
+
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        try_normalize_axis(self.dim, arg.len())?;
+        if self.index.size().len() != arg.len() {
+            return Err(RaxError::shape("gather", arg, self.index.size()));
+        }
+        Ok(self.index.size().to_vec())
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(arg.size()).scatter_add(self.dim, &self.index, &upstream))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let zeros = constant(Tensor::zeros(arg.shape()));
+        scatter_add(zeros, self.dim, &self.index, upstream)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.gather(self.dim, &self.index))
+    }
+
+    /// every example gathers with the same `index`
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let index = broadcast_to_batch(&self.index, self.index.size(), &arg);
+        gather(arg, batch_dim(self.dim), &index)
+    }
+
+    fn name(&self) -> &'static str {
+        "Gather"
+    }
+}
+
+#[derive(Debug)]
+pub struct MaskedSelectOp {
+    mask: Tensor,
+}
+impl UnaryOp for MaskedSelectOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        arg.try_masked_select(&self.mask)
+    }
+
+    /// the mask is known up front, so the number of elements picked is too
+    fn shape(&self, arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        let mask = self
+            .mask
+            .try_expand(arg)
+            .map_err(|_| RaxError::shape("masked_select", arg, self.mask.size()))?;
+        Ok(vec![mask.to_vec().iter().filter(|m| **m != 0.).count()])
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(arg.size()).masked_scatter(&self.mask, &upstream))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        let op = MaskedScatterOp {
+            mask: self.mask.clone(),
+            shape: arg.shape().to_vec(),
+        };
+        Node::new_unr_res(op, upstream)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tangent.masked_select(&self.mask))
+    }
+
+    /// every example selects as many elements, so the result is [batch, n selected]
+    fn batch(&self, arg: Rc<Node>, out: &Tensor) -> Rc<Node> {
+        let mask = broadcast_to_batch(&self.mask, &arg.shape()[1..], &arg);
+        let shape = batch_shape(&arg, out.size());
+        reshape(masked_select(arg, &mask), &shape)
+    }
+
+    fn name(&self) -> &'static str {
+        "MaskedSelect"
+    }
+}
+
+#[derive(Debug)]
+pub struct ScatterAddOp {
+    dim: isize,
+    index: Tensor,
+}
+impl BinaryOp for ScatterAddOp {
+    /// the base tensor passes the gradient straight through, and each `src`
+    /// element gets the gradient of wherever it was added to
+    fn get_grads(&self, upstream: Rc<Tensor>, _args: (Rc<Tensor>, Rc<Tensor>)) -> (Rc<Tensor>, Rc<Tensor>) {
+        (upstream.clone(), Rc::new(upstream.gather(self.dim, &self.index)))
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, _args: (Rc<Node>, Rc<Node>), _out: Rc<Node>) -> (Rc<Node>, Rc<Node>) {
+        (upstream.clone(), gather(upstream, self.dim, &self.index))
+    }
+    fn tangent(&self, _args: (Rc<Tensor>, Rc<Tensor>), (tl, tr): (Rc<Tensor>, Rc<Tensor>), _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(tl.scatter_add(self.dim, &self.index, &tr))
+    }
+    fn batch(&self, args: (Rc<Node>, Rc<Node>), batched: (bool, bool), _out: &Tensor) -> Rc<Node> {
+        let mut args = batch_all(vec![args.0, args.1], vec![batched.0, batched.1]).into_iter();
+        let (x, src) = (args.next().unwrap(), args.next().unwrap());
+        let index = broadcast_to_batch(&self.index, self.index.size(), &x);
+        scatter_add(x, batch_dim(self.dim), &index, src)
+    }
+    fn name(&self) -> &'static str {
+        "ScatterAdd"
+    }
+    fn forward(&self, left: Rc<Tensor>, right: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        left.try_scatter_add(self.dim, &self.index, &right)
+    }
+    fn shape(&self, left: &[usize], right: &[usize]) -> Result<Vec<usize>, RaxError> {
+        try_normalize_axis(self.dim, left.len())?;
+        if self.index.size() != right {
+            return Err(RaxError::shape("scatter_add", self.index.size(), right));
+        }
+        if right.len() != left.len() {
+            return Err(RaxError::shape("scatter_add", left, right));
+        }
+        Ok(left.to_vec())
+    }
+}
+
+/// zeros of `shape` with `src` written into the region `ranges` selects. only
+/// built by the gradient graph of `SliceOp`, so that it can be differentiated again.
+#[derive(Debug)]
+pub struct SliceScatterOp {
+    ranges: Vec<SliceRange>,
+    shape: Vec<usize>,
+}
+impl UnaryOp for SliceScatterOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::zeros(&self.shape).slice_scatter(&self.ranges, &arg))
+    }
+
+    fn shape(&self, _arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(self.shape.clone())
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.slice(&self.ranges))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        slice(upstream, &self.ranges)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(&self.shape).slice_scatter(&self.ranges, &tangent))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let op = SliceScatterOp {
+            ranges: batch_ranges(&self.ranges),
+            shape: batch_shape(&arg, &self.shape),
+        };
+        Node::new_unr_res(op, arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "SliceScatter"
+    }
+}
+
+/// zeros of `shape` with `src` added in at `indices` along `dim`, the gradient of `IndexSelectOp`
+#[derive(Debug)]
+pub struct IndexAddOp {
+    dim: isize,
+    indices: Vec<usize>,
+    shape: Vec<usize>,
+}
+impl UnaryOp for IndexAddOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::zeros(&self.shape).index_add(self.dim, &self.indices, &arg))
+    }
+
+    fn shape(&self, _arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(self.shape.clone())
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.index_select(self.dim, &self.indices))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        index_select(upstream, self.dim, &self.indices)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(&self.shape).index_add(self.dim, &self.indices, &tangent))
+    }
+
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let op = IndexAddOp {
+            dim: batch_dim(self.dim),
+            indices: self.indices.clone(),
+            shape: batch_shape(&arg, &self.shape),
+        };
+        Node::new_unr_res(op, arg)
+    }
+
+    fn name(&self) -> &'static str {
+        "IndexAdd"
+    }
+}
+
+/// zeros of `shape` with the positions set in `mask` filled from `src`, the
+/// gradient of `MaskedSelectOp`
+#[derive(Debug)]
+pub struct MaskedScatterOp {
+    mask: Tensor,
+    shape: Vec<usize>,
+}
+impl UnaryOp for MaskedScatterOp {
+    fn forward(&self, arg: Rc<Tensor>) -> Result<Tensor, RaxError> {
+        Ok(Tensor::zeros(&self.shape).masked_scatter(&self.mask, &arg))
+    }
+
+    fn shape(&self, _arg: &[usize]) -> Result<Vec<usize>, RaxError> {
+        Ok(self.shape.clone())
+    }
+
+    fn get_grads(&self, upstream: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(upstream.masked_select(&self.mask))
+    }
+
+    fn grad_graph(&self, upstream: Rc<Node>, _arg: Rc<Node>, _out: Rc<Node>) -> Rc<Node> {
+        masked_select(upstream, &self.mask)
+    }
+
+    fn tangent(&self, tangent: Rc<Tensor>, _arg: Rc<Tensor>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::zeros(&self.shape).masked_scatter(&self.mask, &tangent))
+    }
+
+    /// `src` is flattened, as the examples' elements are filled in one after the other
+    fn batch(&self, arg: Rc<Node>, _out: &Tensor) -> Rc<Node> {
+        let op = MaskedScatterOp {
+            mask: broadcast_to_batch(&self.mask, &self.shape, &arg),
+            shape: batch_shape(&arg, &self.shape),
+        };
+        Node::new_unr_res(op, flatten(arg, 0, -1))
+    }
+
+    fn name(&self) -> &'static str {
+        "MaskedScatter"
+    }
+}
+
+#[derive(Debug)]
+pub struct CatOp {
+    dim: isize,
+}
+impl NaryOp for CatOp {
+    /// each argument gets back the stretch of the upstream gradient it filled
+    fn get_grads(&self, upstream: Rc<Tensor>, args: Vec<Rc<Tensor>>) -> Vec<Rc<Tensor>> {
+        let dim = self.dim.rem_euclid(upstream.size().len() as isize) as usize;
+        let mut start = 0;
+        args.iter()
+            .map(|arg| {
+                let len = arg.size()[dim];
+                let g = upstream.narrow(dim as isize, start, len);
+                start += len;
+                Rc::new(g)
+            })
+            .collect()
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, args: Vec<Rc<Node>>, _out: Rc<Node>) -> Vec<Rc<Node>> {
+        let dim = self.dim.rem_euclid(upstream.shape().len() as isize) as usize;
+        let mut start = 0;
+        args.iter()
+            .map(|arg| {
+                let len = arg.shape()[dim];
+                let mut ranges = vec![SliceRange::from(..); dim];
+                ranges.push((start..start + len).into());
+                start += len;
+                slice(upstream.clone(), &ranges)
+            })
+            .collect()
+    }
+    fn tangent(&self, tangents: Vec<Rc<Tensor>>, _args: Vec<Rc<Tensor>>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::cat(&tangents.iter().map(|t| t.as_ref()).collect::<Vec<&Tensor>>(), self.dim))
+    }
+    fn batch(&self, args: Vec<Rc<Node>>, batched: Vec<bool>, _out: &Tensor) -> Rc<Node> {
+        cat(&batch_all(args, batched), batch_dim(self.dim))
+    }
+    fn name(&self) -> &'static str {
+        "Cat"
+    }
+    fn forward(&self, args: Vec<Rc<Tensor>>) -> Result<Tensor, RaxError> {
+        Tensor::try_cat(&args.iter().map(|a| a.as_ref()).collect::<Vec<&Tensor>>(), self.dim)
+    }
+
+    fn shape(&self, args: &[&[usize]]) -> Result<Vec<usize>, RaxError> {
+        let Some(first) = args.first() else {
+            return Err(RaxError::shape("cat", &[], &[]));
+        };
+        let dim = try_normalize_axis(self.dim, first.len())?;
+        let mut shape = first.to_vec();
+        shape[dim] = 0;
+        for arg in args {
+            let fits = arg.len() == first.len()
+                && std::iter::zip(*arg, *first).enumerate().all(|(d, (a, b))| d == dim || a == b);
+            if !fits {
+                return Err(RaxError::shape("cat", first, arg));
+            }
+            shape[dim] += arg[dim];
+        }
+        Ok(shape)
+    }
+}
+
+#[derive(Debug)]
+pub struct StackOp {
+    dim: isize,
+}
+impl NaryOp for StackOp {
+    fn get_grads(&self, upstream: Rc<Tensor>, args: Vec<Rc<Tensor>>) -> Vec<Rc<Tensor>> {
+        let dim = self.dim.rem_euclid(upstream.size().len() as isize);
+        (0..args.len())
+            .map(|i| Rc::new(upstream.narrow(dim, i, 1).squeeze(dim)))
+            .collect()
+    }
+    fn grad_graph(&self, upstream: Rc<Node>, args: Vec<Rc<Node>>, _out: Rc<Node>) -> Vec<Rc<Node>> {
+        let dim = self.dim.rem_euclid(upstream.shape().len() as isize);
+        (0..args.len())
+            .map(|i| {
+                let mut ranges = vec![SliceRange::from(..); dim as usize];
+                ranges.push((i..i + 1).into());
+                squeeze(slice(upstream.clone(), &ranges), dim)
+            })
+            .collect()
+    }
+    fn tangent(&self, tangents: Vec<Rc<Tensor>>, _args: Vec<Rc<Tensor>>, _out: Rc<Tensor>) -> Rc<Tensor> {
+        Rc::new(Tensor::stack(&tangents.iter().map(|t| t.as_ref()).collect::<Vec<&Tensor>>(), self.dim))
+    }
+    fn batch(&self, args: Vec<Rc<Node>>, batched: Vec<bool>, _out: &Tensor) -> Rc<Node> {
+        stack(&batch_all(args, batched), batch_dim(self.dim))
+    }
+    fn name(&self) -> &'static str {
+        "Stack"
+    }
+    fn forward(&self, args: Vec<Rc<Tensor>>) -> Result<Tensor, RaxError> {
+        Tensor::try_stack(&args.iter().map(|a| a.as_ref()).collect::<Vec<&Tensor>>(), self.dim)
+    }
+
+    fn shape(&self, args: &[&[usize]]) -> Result<Vec<usize>, RaxError> {
+        let Some(first) = args.first() else {
+            return Err(RaxError::shape("stack", &[], &[]));
+        };
+        if let Some(arg) = args.iter().find(|arg| *arg != first) {
+            return Err(RaxError::shape("stack", first, arg));
+        }
+        let mut shape = first.to_vec();
+        shape.insert(try_normalize_axis(self.dim, first.len() + 1)?, args.len());
+        Ok(shape)
+    }
+}
+
+// GRADIENT GRAPH HELPERS
+//
+
+/// a node for `t` that no gradient flows into
+fn constant(t: Tensor) -> Rc<Node> {
+    Rc::new(Node::Const(t))
+}
+
+/// 1 - x
+fn one_minus(x: Rc<Node>) -> Rc<Node> {
+    add_scalar(neg(x), 1.)
+}
+
+/// sums `x` over the dimensions that were broadcast to get from `shape` to its
+/// shape, the graph version of `Tensor::unbroadcast`
+pub(crate) fn sum_to(x: Rc<Node>, shape: &[usize]) -> Rc<Node> {
+    let x_shape = x.shape().to_vec();
+    if x_shape == shape {
+        return x;
+    }
+    let lead = x_shape.len() - shape.len();
+    let mut axes: Vec<isize> = (0..lead as isize).collect();
+    axes.extend((0..shape.len()).filter(|&d| shape[d] == 1 && x_shape[lead + d] != 1).map(|d| (lead + d) as isize));
+    if axes.is_empty() {
+        return reshape(x, shape);
+    }
+    reshape(sum(x, &axes, true), shape)
+}
+
+/// broadcasts `x`, reduced over `axes`, back to the shape of `arg`
+fn expand_reduced(x: Rc<Node>, axes: &[isize], arg: &Rc<Node>) -> Rc<Node> {
+    let arg_shape = arg.shape().to_vec();
+    let keepdim_shape = x.val().unsqueeze_reduced(axes, arg_shape.len()).size().to_vec();
+    expand(reshape(x, &keepdim_shape), &arg_shape)
+}
+
+// SHAPE HELPERS
+//
+// for `shape`, which works out an op's output shape without computing it
+
+/// a tensor of shape `shape` holding a single element, to try views on
+fn probe(shape: &[usize]) -> Tensor {
+    Tensor::from(0.).expand(shape)
+}
+
+/// see `Tensor::sum`
+fn reduced_shape(shape: &[usize], axes: &[isize], keepdim: bool) -> Result<Vec<usize>, RaxError> {
+    let axes = axes
+        .iter()
+        .map(|axis| try_normalize_axis(*axis, shape.len()))
+        .collect::<Result<Vec<usize>, RaxError>>()?;
+    let reduced = |d: usize| axes.is_empty() || axes.contains(&d);
+    Ok(shape
+        .iter()
+        .enumerate()
+        .filter_map(|(d, n)| match (reduced(d), keepdim) {
+            (false, _) => Some(*n),
+            (true, true) => Some(1),
+            (true, false) => None,
+        })
+        .collect())
+}
+
+/// see `Tensor::matmul`
+fn matmul_shape(l: &[usize], r: &[usize]) -> Result<Vec<usize>, RaxError> {
+    let mismatch = || RaxError::shape("matmul", l, r);
+    if l.is_empty() || r.is_empty() {
+        return Err(mismatch());
+    }
+    let l_p = if l.len() == 1 { vec![1, l[0]] } else { l.to_vec() };
+    let r_p = if r.len() == 1 { vec![r[0], 1] } else { r.to_vec() };
+    let (l_nd, r_nd) = (l_p.len(), r_p.len());
+    if l_p[l_nd - 1] != r_p[r_nd - 2] {
+        return Err(mismatch());
+    }
+    let mut shape = broadcast_shapes(&l_p[..l_nd - 2], &r_p[..r_nd - 2]).ok_or_else(mismatch)?;
+    if l.len() > 1 {
+        shape.push(l_p[l_nd - 2]);
+    }
+    if r.len() > 1 {
+        shape.push(r_p[r_nd - 1]);
+    }
+    Ok(shape)
+}
+
+// BATCHING HELPERS
+//
+// a batched node has the batch axis in front of the axes of one example
+
+fn batch_size(x: &Rc<Node>) -> usize {
+    x.shape()[0]
+}
+
+/// `dim` of an example, as a dimension of the batch. negative dims count from
+/// the back, so they don't move
+fn batch_dim(dim: isize) -> isize {
+    if dim < 0 {
+        dim
+    } else {
+        dim + 1
+    }
+}
+
+/// the batch axis of `x` followed by `shape`
+fn batch_shape(x: &Rc<Node>, shape: &[usize]) -> Vec<usize> {
+    [&[batch_size(x)], shape].concat()
+}
+
+fn batch_ranges(ranges: &[SliceRange]) -> Vec<SliceRange> {
+    std::iter::once(SliceRange::from(..)).chain(ranges.iter().cloned()).collect()
+}
+
+/// inserts size-1 axes after the batch axis of `x` until an example has `rank`
+/// axes, so that it broadcasts like the unbatched example would
+fn with_rank(x: Rc<Node>, rank: usize) -> Rc<Node> {
+    let shape = x.shape().to_vec();
+    if shape.len() > rank {
+        return x;
+    }
+    let mut padded = vec![shape[0]];
+    padded.extend(std::iter::repeat_n(1, rank + 1 - shape.len()));
+    padded.extend(&shape[1..]);
+    reshape(x, &padded)
+}
+
+/// copies `x` along a new batch axis of size `n_batch`
+pub(crate) fn expand_batch(x: Rc<Node>, n_batch: usize) -> Rc<Node> {
+    let shape = [&[n_batch], x.shape()].concat();
+    expand(unsqueeze(x, 0), &shape)
+}
+
+/// a per-example tensor (an index or a mask) broadcast to `shape`, and then
+/// along the batch axis of `batched`
+fn broadcast_to_batch(t: &Tensor, shape: &[usize], batched: &Rc<Node>) -> Tensor {
+    t.expand(shape).unsqueeze(0).expand(&batch_shape(batched, shape))
+}
+
+fn align_elementwise((l, r): (Rc<Node>, Rc<Node>), batched: (bool, bool), out: &Tensor) -> (Rc<Node>, Rc<Node>) {
+    let rank = out.size().len();
+    let l = if batched.0 { with_rank(l, rank) } else { l };
+    let r = if batched.1 { with_rank(r, rank) } else { r };
+    (l, r)
+}
+
+/// gives every argument a batch axis
+fn batch_all(args: Vec<Rc<Node>>, batched: Vec<bool>) -> Vec<Rc<Node>> {
+    let n_batch = std::iter::zip(&args, &batched)
+        .find(|(_, b)| **b)
+        .map(|(arg, _)| batch_size(arg))
+        .expect("batch_all: no batched argument");
+    std::iter::zip(args, batched)
+        .map(|(arg, b)| if b { arg } else { expand_batch(arg, n_batch) })
+        .collect()
+}
+
+/// `axes` of an example (all of them if empty) as axes of the batch, keeping
+/// them if the unbatched reduction did
+fn batch_reduce(
+    reduce: fn(Rc<Node>, &[isize], bool) -> Rc<Node>,
+    axes: &[isize],
+    arg: Rc<Node>,
+    out: &Tensor,
+) -> Rc<Node> {
+    let rank = arg.shape().len() - 1;
+    if rank == 0 {
+        // reducing a scalar leaves it as it is
+        return arg;
+    }
+    let axes: Vec<isize> = if axes.is_empty() {
+        (1..=rank as isize).collect()
+    } else {
+        axes.iter().map(|a| batch_dim(*a)).collect()
+    };
+    reduce(arg, &axes, out.size().len() == rank)
+}
+
+macro_rules! create_binary_op {
+    ($name:ident, $try_name:ident, $op:ident) => {
+        pub fn $name(l: Rc<Node>, r: Rc<Node>) -> Rc<Node> {
+            Node::new_bin_res($op, l, r)
+        }
+
+        pub fn $try_name(l: Rc<Node>, r: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+            Node::try_new_bin_res($op, l, r)
+        }
+    };
+}
+
+create_binary_op!(add, try_add, AddOp);
+create_binary_op!(mul, try_mul, MulOp);
+create_binary_op!(mmul, try_mmul, MMulOp);
+create_binary_op!(sub, try_sub, SubOp);
+create_binary_op!(div, try_div, DivOp);
+create_binary_op!(maximum, try_maximum, MaxOp);
+create_binary_op!(minimum, try_minimum, MinOp);
+create_binary_op!(pow_tensor, try_pow_tensor, PowOp);
+
+// UNARY
+//
+
+pub fn sqr(x: Rc<Node>) -> Rc<Node> {
+    Node::new_unr_res(SqrOp, x)
+}
+
+pub fn neg(x: Rc<Node>) -> Rc<Node> {
+    Node::new_unr_res(NegOp, x)
+}
+
+pub fn relu(x: Rc<Node>) -> Rc<Node> {
+    Node::new_unr_res(ReluOp::default(), x)
+}
+
+macro_rules! create_unary_op {
+    ($name:ident, $op:ident) => {
+        pub fn $name(x: Rc<Node>) -> Rc<Node> {
+            Node::new_unr_res($op, x)
+        }
+    };
+}
+
+create_unary_op!(exp, ExpOp);
+create_unary_op!(log, LogOp);
+create_unary_op!(sqrt, SqrtOp);
+create_unary_op!(rsqrt, RsqrtOp);
+create_unary_op!(abs, AbsOp);
+create_unary_op!(sin, SinOp);
+create_unary_op!(cos, CosOp);
+create_unary_op!(reciprocal, ReciprocalOp);
+
+pub fn pow(x: Rc<Node>, p: f64) -> Rc<Node> {
+    Node::new_unr_res(PowScalarOp { p }, x)
+}
+
+pub fn mul_scalar(x: Rc<Node>, c: f64) -> Rc<Node> {
+    Node::new_unr_res(MulScalarOp { c }, x)
+}
+
+pub fn add_scalar(x: Rc<Node>, c: f64) -> Rc<Node> {
+    Node::new_unr_res(AddScalarOp { c }, x)
+}
+
+create_unary_op!(xlogx, XLogXOp);
+
+// ACTIVATIONS
+//
+
+create_unary_op!(sigmoid, SigmoidOp);
+create_unary_op!(tanh, TanhOp);
+create_unary_op!(gelu, GeluOp);
+create_unary_op!(silu, SiluOp);
+create_unary_op!(softplus, SoftplusOp);
+
+pub fn leaky_relu(x: Rc<Node>, slope: f64) -> Rc<Node> {
+    Node::new_unr_res(LeakyReluOp { slope }, x)
+}
+
+pub fn elu(x: Rc<Node>, alpha: f64) -> Rc<Node> {
+    Node::new_unr_res(EluOp { alpha }, x)
+}
+
+pub fn softmax(x: Rc<Node>, dim: isize) -> Rc<Node> {
+    Node::new_unr_res(SoftmaxOp { dim }, x)
+}
+
+pub fn try_softmax(x: Rc<Node>, dim: isize) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(SoftmaxOp { dim }, x)
+}
+
+pub fn log_softmax(x: Rc<Node>, dim: isize) -> Rc<Node> {
+    Node::new_unr_res(LogSoftmaxOp { dim }, x)
+}
+
+pub fn try_log_softmax(x: Rc<Node>, dim: isize) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(LogSoftmaxOp { dim }, x)
+}
+
+/// reduces `dim` away, see `Tensor::logsumexp`
+pub fn logsumexp(x: Rc<Node>, dim: isize) -> Rc<Node> {
+    Node::new_unr_res(LogSumExpOp { dim }, x)
+}
+
+pub fn try_logsumexp(x: Rc<Node>, dim: isize) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(LogSumExpOp { dim }, x)
+}
+
+// REDUCE
+// 
+
+macro_rules! create_reduce_op {
+    ($name:ident, $try_name:ident, $op:ident) => {
+        /// reduces over `axes` (all axes if empty), see `Tensor::sum`
+        pub fn $name(x: Rc<Node>, axes: &[isize], keepdim: bool) -> Rc<Node> {
+            $try_name(x, axes, keepdim).or_panic()
+        }
+
+        /// errors if any of `axes` isn't a dimension of `x`
+        pub fn $try_name(x: Rc<Node>, axes: &[isize], keepdim: bool) -> Result<Rc<Node>, RaxError> {
+            Node::try_new_red_res($op { axes: axes.to_vec(), keepdim }, x)
+        }
+    };
+}
+
+create_reduce_op!(sum, try_sum, SumOp);
+create_reduce_op!(mean, try_mean, MeanOp);
+create_reduce_op!(max, try_max, ReduceMaxOp);
+create_reduce_op!(min, try_min, ReduceMinOp);
+create_reduce_op!(prod, try_prod, ProdOp);
+
+// SHAPE
+//
+// each op has a `try_` variant that returns the error its panicking
+// counterpart would panic with
+
+pub fn reshape(x: Rc<Node>, shape: &[usize]) -> Rc<Node> {
+    Node::new_unr_res(ReshapeOp { shape: shape.to_vec() }, x)
+}
+
+pub fn try_reshape(x: Rc<Node>, shape: &[usize]) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(ReshapeOp { shape: shape.to_vec() }, x)
+}
+
+/// like `reshape`, but panics if the value isn't contiguous
+pub fn view(x: Rc<Node>, shape: &[usize]) -> Rc<Node> {
+    try_view(x, shape).or_panic()
+}
+
+/// a node that isn't computed yet (see `lazy`) can't be checked for
+/// contiguity, so only its shape is
+pub fn try_view(x: Rc<Node>, shape: &[usize]) -> Result<Rc<Node>, RaxError> {
+    if let Some(val) = x.cached() {
+        val.try_view(shape)?;
+    }
+    Node::try_new_unr_res(ReshapeOp { shape: shape.to_vec() }, x)
+}
+
+pub fn flatten(x: Rc<Node>, start: isize, end: isize) -> Rc<Node> {
+    try_flatten(x, start, end).or_panic()
+}
+
+pub fn try_flatten(x: Rc<Node>, start: isize, end: isize) -> Result<Rc<Node>, RaxError> {
+    let shape = probe(x.shape()).flattened_shape(start, end)?;
+    Node::try_new_unr_res(ReshapeOp { shape }, x)
+}
+
+pub fn squeeze(x: Rc<Node>, dim: isize) -> Rc<Node> {
+    try_squeeze(x, dim).or_panic()
+}
+
+pub fn try_squeeze(x: Rc<Node>, dim: isize) -> Result<Rc<Node>, RaxError> {
+    let shape = probe(x.shape()).try_squeeze(dim)?.size().to_vec();
+    Node::try_new_unr_res(ReshapeOp { shape }, x)
+}
+
+pub fn unsqueeze(x: Rc<Node>, dim: isize) -> Rc<Node> {
+    try_unsqueeze(x, dim).or_panic()
+}
+
+pub fn try_unsqueeze(x: Rc<Node>, dim: isize) -> Result<Rc<Node>, RaxError> {
+    let shape = probe(x.shape()).try_unsqueeze(dim)?.size().to_vec();
+    Node::try_new_unr_res(ReshapeOp { shape }, x)
+}
+
+pub fn expand(x: Rc<Node>, shape: &[usize]) -> Rc<Node> {
+    Node::new_unr_res(ExpandOp { shape: shape.to_vec() }, x)
+}
+
+pub fn try_expand(x: Rc<Node>, shape: &[usize]) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(ExpandOp { shape: shape.to_vec() }, x)
+}
+
+pub fn permute(x: Rc<Node>, dims: &[isize]) -> Rc<Node> {
+    Node::new_unr_res(PermuteOp { dims: dims.to_vec() }, x)
+}
+
+pub fn try_permute(x: Rc<Node>, dims: &[isize]) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(PermuteOp { dims: dims.to_vec() }, x)
+}
+
+pub fn transpose(x: Rc<Node>, dim_1: isize, dim_2: isize) -> Rc<Node> {
+    try_transpose(x, dim_1, dim_2).or_panic()
+}
+
+pub fn try_transpose(x: Rc<Node>, dim_1: isize, dim_2: isize) -> Result<Rc<Node>, RaxError> {
+    let rank = x.shape().len();
+    let mut dims: Vec<isize> = (0..rank as isize).collect();
+    dims.swap(try_normalize_axis(dim_1, rank)?, try_normalize_axis(dim_2, rank)?);
+    try_permute(x, &dims)
+}
+
+// INDEXING
+//
+
+pub fn slice(x: Rc<Node>, ranges: &[SliceRange]) -> Rc<Node> {
+    Node::new_unr_res(SliceOp { ranges: ranges.to_vec() }, x)
+}
+
+pub fn try_slice(x: Rc<Node>, ranges: &[SliceRange]) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(SliceOp { ranges: ranges.to_vec() }, x)
+}
+
+pub fn index_select(x: Rc<Node>, dim: isize, indices: &[usize]) -> Rc<Node> {
+    try_index_select(x, dim, indices).or_panic()
+}
+
+pub fn try_index_select(x: Rc<Node>, dim: isize, indices: &[usize]) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(
+        IndexSelectOp {
+            dim,
+            indices: indices.to_vec(),
+        },
+        x,
+    )
+}
+
+pub fn gather(x: Rc<Node>, dim: isize, index: &Tensor) -> Rc<Node> {
+    try_gather(x, dim, index).or_panic()
+}
+
+pub fn try_gather(x: Rc<Node>, dim: isize, index: &Tensor) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(
+        GatherOp {
+            dim,
+            index: index.clone(),
+        },
+        x,
+    )
+}
+
+pub fn scatter_add(x: Rc<Node>, dim: isize, index: &Tensor, src: Rc<Node>) -> Rc<Node> {
+    try_scatter_add(x, dim, index, src).or_panic()
+}
+
+pub fn try_scatter_add(x: Rc<Node>, dim: isize, index: &Tensor, src: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_bin_res(
+        ScatterAddOp {
+            dim,
+            index: index.clone(),
+        },
+        x,
+        src,
+    )
+}
+
+/// `a` where `cond` is nonzero, `b` elsewhere. the gradient only flows to the
+/// branch that was picked.
+pub fn where_(cond: &Tensor, a: Rc<Node>, b: Rc<Node>) -> Rc<Node> {
+    Node::new_bin_res(WhereOp { cond: cond.clone() }, a, b)
+}
+
+pub fn try_where_(cond: &Tensor, a: Rc<Node>, b: Rc<Node>) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_bin_res(WhereOp { cond: cond.clone() }, a, b)
+}
+
+pub fn masked_select(x: Rc<Node>, mask: &Tensor) -> Rc<Node> {
+    Node::new_unr_res(MaskedSelectOp { mask: mask.clone() }, x)
+}
+
+pub fn try_masked_select(x: Rc<Node>, mask: &Tensor) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_unr_res(MaskedSelectOp { mask: mask.clone() }, x)
+}
+
+// JOINING / SPLITTING
+//
+
+pub fn cat(xs: &[Rc<Node>], dim: isize) -> Rc<Node> {
+    Node::new_nary_res(CatOp { dim }, xs.to_vec())
+}
+
+pub fn try_cat(xs: &[Rc<Node>], dim: isize) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_nary_res(CatOp { dim }, xs.to_vec())
+}
+
+pub fn stack(xs: &[Rc<Node>], dim: isize) -> Rc<Node> {
+    Node::new_nary_res(StackOp { dim }, xs.to_vec())
+}
+
+pub fn try_stack(xs: &[Rc<Node>], dim: isize) -> Result<Rc<Node>, RaxError> {
+    Node::try_new_nary_res(StackOp { dim }, xs.to_vec())
+}
+
+/// see `Tensor::split`. each piece is a `slice` of `x`.
+pub fn split(x: Rc<Node>, split_size: usize, dim: isize) -> Vec<Rc<Node>> {
+    try_split(x, split_size, dim).or_panic()
+}
+
+pub fn try_split(x: Rc<Node>, split_size: usize, dim: isize) -> Result<Vec<Rc<Node>>, RaxError> {
+    if split_size == 0 {
+        return Err(RaxError::invalid("split", "split size must be positive"));
+    }
+    let shape = x.shape().to_vec();
+    let dim = try_normalize_axis(dim, shape.len())?;
+    (0..shape[dim])
+        .step_by(split_size)
+        .map(|start| {
+            let mut ranges = vec![SliceRange::from(..); dim];
+            ranges.push((start..start + split_size).into());
+            try_slice(x.clone(), &ranges)
+        })
+        .collect()
+}
+
+/// see `Tensor::chunk`
+pub fn chunk(x: Rc<Node>, n_chunks: usize, dim: isize) -> Vec<Rc<Node>> {
+    try_chunk(x, n_chunks, dim).or_panic()
+}
+
+pub fn try_chunk(x: Rc<Node>, n_chunks: usize, dim: isize) -> Result<Vec<Rc<Node>>, RaxError> {
+    if n_chunks == 0 {
+        return Err(RaxError::invalid("chunk", "number of chunks must be positive"));
+    }
+    let dim_size = x.shape()[try_normalize_axis(dim, x.shape().len())?];
+    try_split(x, dim_size.div_ceil(n_chunks).max(1), dim)
+}
@@ -1,10 +1,16 @@
 use std::collections::HashMap;
 
 use crate::backward::GradMap;
+use crate::error::{OrPanic, RaxError};
 use crate::tensor::Tensor;
 
 pub trait Optimizer {
-    fn update(&self, params: ParamsMap, grads: GradMap) -> ParamsMap;
+    /// errors if a parameter has no gradient, or one of the wrong shape
+    fn try_update(&self, params: ParamsMap, grads: GradMap) -> Result<ParamsMap, RaxError>;
+
+    fn update(&self, params: ParamsMap, grads: GradMap) -> ParamsMap {
+        self.try_update(params, grads).or_panic()
+    }
 }
 
 #[derive(Debug)]
@@ -26,14 +32,19 @@ pub struct SGD {
 }
 
 impl Optimizer for SGD {
-    fn update(&self, mut params: ParamsMap, grads: GradMap) -> ParamsMap {
+    fn try_update(&self, mut params: ParamsMap, grads: GradMap) -> Result<ParamsMap, RaxError> {
         for (name, param) in params.0.iter_mut() {
-            let grad = grads.get(name).unwrap();
-            let update = Tensor::mul(grad, &Tensor::from(self.lr)).unwrap();
-            let new = Tensor::sub(param, &update).unwrap();
+            let grad = grads
+                .get(name)
+                .ok_or_else(|| RaxError::MissingGradient { param: name.clone() })?;
+            if grad.size() != param.size() {
+                return Err(RaxError::shape("SGD", param.size(), grad.size()));
+            }
+            let update = Tensor::mul(grad, &Tensor::from(self.lr))?;
+            let new = Tensor::sub(param, &update)?;
             *param = new;
         }
-        params
+        Ok(params)
     }
 }
 
@@ -42,4 +53,30 @@ impl Default for SGD {
     fn default() -> Self {
         SGD { lr: DEFAULT_LR }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sgd_errors() {
+        let mut params = ParamsMap::new();
+        params.0.insert("w".to_string(), Tensor::ones(&[2]));
+        let sgd = SGD { lr: 0.5 };
+
+        let err = sgd.try_update(params, GradMap::new()).unwrap_err();
+        assert_eq!(err, RaxError::MissingGradient { param: "w".to_string() });
+        assert_eq!(err.to_string(), "no gradient for parameter `w`");
+
+        let mut params = ParamsMap::new();
+        params.0.insert("w".to_string(), Tensor::ones(&[2]));
+        let grads = GradMap::from([("w".to_string(), Tensor::ones(&[3]))]);
+        assert!(sgd.try_update(params, grads).is_err());
+
+        let mut params = ParamsMap::new();
+        params.0.insert("w".to_string(), Tensor::ones(&[2]));
+        let grads = GradMap::from([("w".to_string(), Tensor::ones(&[2]))]);
+        assert_eq!(sgd.update(params, grads).0["w"].to_vec(), vec![0.5, 0.5]);
+    }
+}
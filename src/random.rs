@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng as _, SeedableRng};
+
+use crate::tensor::Tensor;
+
+/// A random number generator for tensors. The same seed gives the same
+/// tensors, run to run.
+///
+/// Either keep an explicit `Rng` around, or use the free functions in this
+/// module, which draw from a per-thread global generator (see `manual_seed`).
+#[derive(Clone, Debug)]
+pub struct Rng(StdRng);
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Rng {
+        Rng(StdRng::seed_from_u64(seed))
+    }
+
+    /// seeded from the OS, so not reproducible
+    pub fn from_entropy() -> Rng {
+        Rng(StdRng::from_entropy())
+    }
+
+    /// uniform on [0, 1)
+    pub fn rand(&mut self, shape: &[usize]) -> Tensor {
+        self.uniform(shape, 0., 1.)
+    }
+
+    /// uniform on [lo, hi)
+    pub fn uniform(&mut self, shape: &[usize], lo: f64, hi: f64) -> Tensor {
+        let data = (0..n_elements(shape)).map(|_| lo + (hi - lo) * self.0.gen::<f64>()).collect();
+        Tensor::new(data, shape)
+    }
+
+    /// standard normal
+    pub fn randn(&mut self, shape: &[usize]) -> Tensor {
+        self.normal(shape, 0., 1.)
+    }
+
+    pub fn normal(&mut self, shape: &[usize], mean: f64, std: f64) -> Tensor {
+        let data = (0..n_elements(shape)).map(|_| mean + std * self.standard_normal()).collect();
+        Tensor::new(data, shape)
+    }
+
+    /// normal, with samples outside [lo, hi] redrawn. `lo` and `hi` are values,
+    /// not numbers of standard deviations. redrawing is slow if [lo, hi] is
+    /// far out in a tail.
+    pub fn trunc_normal(&mut self, shape: &[usize], mean: f64, std: f64, lo: f64, hi: f64) -> Tensor {
+        assert!(lo < hi, "empty truncation range [{}, {}]", lo, hi);
+        let data = (0..n_elements(shape))
+            .map(|_| loop {
+                let v = mean + std * self.standard_normal();
+                if (lo..=hi).contains(&v) {
+                    break v;
+                }
+            })
+            .collect();
+        Tensor::new(data, shape)
+    }
+
+    /// a bool tensor, each element `true` with probability `p`
+    pub fn bernoulli(&mut self, shape: &[usize], p: f64) -> Tensor {
+        assert!((0. ..=1.).contains(&p), "bernoulli probability {} isn't in [0, 1]", p);
+        let data = (0..n_elements(shape)).map(|_| self.0.gen::<f64>() < p).collect();
+        Tensor::from_vec(data, shape)
+    }
+
+    /// an i64 tensor, uniform on the integers in [lo, hi)
+    pub fn randint(&mut self, shape: &[usize], lo: i64, hi: i64) -> Tensor {
+        assert!(lo < hi, "empty range {}..{}", lo, hi);
+        let data = (0..n_elements(shape)).map(|_| self.0.gen_range(lo..hi)).collect();
+        Tensor::from_vec(data, shape)
+    }
+
+    /// a random ordering of 0..n, as an i64 tensor
+    pub fn randperm(&mut self, n: usize) -> Tensor {
+        let mut data: Vec<i64> = (0..n as i64).collect();
+        data.shuffle(&mut self.0);
+        Tensor::from_vec(data, &[n])
+    }
+
+    /// Box-Muller, keeping one of the pair
+    fn standard_normal(&mut self) -> f64 {
+        // 1 - u is in (0, 1], so the log is finite
+        let u1 = 1. - self.0.gen::<f64>();
+        let u2 = self.0.gen::<f64>();
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+}
+
+fn n_elements(shape: &[usize]) -> usize {
+    shape.iter().product()
+}
+
+thread_local! {
+    static GLOBAL: RefCell<Rng> = RefCell::new(Rng::from_entropy());
+}
+
+/// Reseeds the global generator of the current thread. Tensors (and graphs)
+/// aren't `Send`, so each thread gets its own.
+pub fn manual_seed(seed: u64) {
+    GLOBAL.with(|rng| *rng.borrow_mut() = Rng::from_seed(seed));
+}
+
+/// runs `f` with the current thread's global generator
+pub fn with_global_rng<T>(f: impl FnOnce(&mut Rng) -> T) -> T {
+    GLOBAL.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn rand(shape: &[usize]) -> Tensor {
+    with_global_rng(|rng| rng.rand(shape))
+}
+
+pub fn uniform(shape: &[usize], lo: f64, hi: f64) -> Tensor {
+    with_global_rng(|rng| rng.uniform(shape, lo, hi))
+}
+
+pub fn randn(shape: &[usize]) -> Tensor {
+    with_global_rng(|rng| rng.randn(shape))
+}
+
+pub fn normal(shape: &[usize], mean: f64, std: f64) -> Tensor {
+    with_global_rng(|rng| rng.normal(shape, mean, std))
+}
+
+pub fn trunc_normal(shape: &[usize], mean: f64, std: f64, lo: f64, hi: f64) -> Tensor {
+    with_global_rng(|rng| rng.trunc_normal(shape, mean, std, lo, hi))
+}
+
+pub fn bernoulli(shape: &[usize], p: f64) -> Tensor {
+    with_global_rng(|rng| rng.bernoulli(shape, p))
+}
+
+pub fn randint(shape: &[usize], lo: i64, hi: i64) -> Tensor {
+    with_global_rng(|rng| rng.randint(shape, lo, hi))
+}
+
+pub fn randperm(n: usize) -> Tensor {
+    with_global_rng(|rng| rng.randperm(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtype::DType;
+
+    fn mean_and_std(t: &Tensor) -> (f64, f64) {
+        let v = t.to_vec();
+        let mean = v.iter().sum::<f64>() / v.len() as f64;
+        let var = v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / v.len() as f64;
+        (mean, var.sqrt())
+    }
+
+    #[test]
+    fn test_seeded() {
+        let (mut a, mut b) = (Rng::from_seed(7), Rng::from_seed(7));
+        assert_eq!(a.randn(&[3, 4]).to_vec(), b.randn(&[3, 4]).to_vec());
+        assert_ne!(a.randn(&[3, 4]).to_vec(), Rng::from_seed(8).randn(&[3, 4]).to_vec());
+
+        manual_seed(3);
+        let first = (rand(&[5]).to_vec(), randperm(5).to_vec());
+        manual_seed(3);
+        assert_eq!(first, (rand(&[5]).to_vec(), randperm(5).to_vec()));
+        manual_seed(3);
+        assert_eq!(first.0, Tensor::rand(&[5]).to_vec());
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Rng::from_seed(0);
+
+        let (mean, std) = mean_and_std(&rng.normal(&[10000], 2., 3.));
+        assert!((mean - 2.).abs() < 0.1 && (std - 3.).abs() < 0.1, "{mean} {std}");
+
+        let u = rng.uniform(&[10000], -1., 3.);
+        assert!(u.to_vec().iter().all(|v| (-1. ..3.).contains(v)));
+        assert!((mean_and_std(&u).0 - 1.).abs() < 0.1);
+
+        let t = rng.trunc_normal(&[1000], 0., 1., -0.5, 2.);
+        assert!(t.to_vec().iter().all(|v| (-0.5..=2.).contains(v)));
+
+        let b = rng.bernoulli(&[10000], 0.3);
+        assert_eq!(b.dtype(), DType::Bool);
+        assert!((mean_and_std(&b).0 - 0.3).abs() < 0.02);
+
+        let i = rng.randint(&[2, 500], -2, 3);
+        assert_eq!(i.dtype(), DType::I64);
+        assert_eq!(i.size(), &[2, 500]);
+        let mut seen = i.to_vec();
+        seen.sort_by(f64::total_cmp);
+        seen.dedup();
+        assert_eq!(seen, vec![-2., -1., 0., 1., 2.]);
+
+        let mut p = rng.randperm(10).to_vec();
+        p.sort_by(f64::total_cmp);
+        assert_eq!(p, (0..10).map(|i| i as f64).collect::<Vec<f64>>());
+    }
+}
@@ -1,15 +1,27 @@
-use rand::Rng;
+use crate::dtype::{DType, Element, Storage};
+use crate::error::{OrPanic, RaxError};
+use crate::random;
 use std::{
+    borrow::Cow,
     fmt::{Debug, Write},
     iter::zip,
+    ops::{Range, RangeFrom, RangeFull, RangeInclusive, RangeTo},
+    rc::Rc,
     vec,
 };
 
-/// NOTE: this is a lightweight wrapper around a potentially huge Vec<f64>.
-/// cloning a `Tensor` is NOT cheap.
+/// A strided view into a reference-counted buffer. Cloning a `Tensor` is cheap,
+/// as are the view methods (`transpose`, `reshape` of a contiguous tensor,
+/// `unsqueeze`, `expand`, `narrow`), which all share the buffer rather than
+/// copying it. Use `contiguous` to materialise a view into its own buffer.
+///
+/// Elements are stored as one of the `DType`s (`f64` unless asked otherwise),
+/// but always read and computed with as `f64`.
 #[derive(Clone)]
 pub struct Tensor {
-    pub(crate) data: Vec<f64>,
+    pub(crate) data: Rc<Storage>,
+    /// where element `[0, 0, ...]` lives in `data`
+    offset: usize,
     shape: Vec<usize>,
     stride: Vec<usize>,
 }
@@ -21,13 +33,19 @@ pub struct VecTensor {
     stride: Vec<usize>,
 }
 
-/// A tensor managed as a vec. As opposed to `Tensor`, which is managed as an Rc<Vec<f64>>.
+/// A tensor managed as a vec. As opposed to `Tensor`, which is managed as an Rc<Storage>.
 impl VecTensor {
-    fn as_tensor(&self) -> Tensor {
+    fn into_tensor(self) -> Tensor {
+        self.into_tensor_as(DType::F64)
+    }
+
+    /// converts the elements to `dtype`, see `Storage::from_f64s`
+    fn into_tensor_as(self, dtype: DType) -> Tensor {
         Tensor {
-            data: self.data.clone(),
-            shape: self.shape.clone(),
-            stride: self.stride.clone(),
+            data: Rc::new(Storage::from_f64s(self.data, dtype)),
+            offset: 0,
+            shape: self.shape,
+            stride: self.stride,
         }
     }
 
@@ -40,12 +58,88 @@ impl VecTensor {
         }
     }
 
+    /// a mutable, contiguous copy of `t`
+    pub fn from_tensor(t: &Tensor) -> VecTensor {
+        VecTensor {
+            data: t.to_vec(),
+            shape: t.shape.clone(),
+            stride: Tensor::get_postfix_prod(&t.shape),
+        }
+    }
+
+    pub fn ones(shape: &[usize]) -> VecTensor {
+        VecTensor::full(shape, 1.)
+    }
+
+    pub fn full(shape: &[usize], value: f64) -> VecTensor {
+        let capacity = shape.iter().product();
+        VecTensor {
+            data: vec![value; capacity],
+            shape: shape.to_vec(),
+            stride: Tensor::get_postfix_prod(shape),
+        }
+    }
+
     pub fn at_mut(&mut self, indices: &Vec<usize>) -> &mut f64 {
         let idx = self.flat_idx(indices);
         self.data.get_mut(idx).unwrap()
     }
 }
 
+/// `start..end` in steps of `step`, along one dimension. Build one from a
+/// range (`(1..3).into()`, `(..).into()`, `(2..).into()`, `(..3).into()`,
+/// `(1..=2).into()`) and optionally add a step with `step_by`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceRange {
+    pub start: usize,
+    pub end: usize,
+    pub step: usize,
+}
+
+impl SliceRange {
+    pub fn step_by(self, step: usize) -> SliceRange {
+        assert!(step > 0, "slice step must be positive");
+        SliceRange { step, ..self }
+    }
+
+    /// (start, number of elements) when slicing a dimension of size `dim_size`
+    fn resolve(&self, dim_size: usize) -> (usize, usize) {
+        let end = self.end.min(dim_size);
+        let start = self.start.min(end);
+        (start, (end - start).div_ceil(self.step))
+    }
+}
+
+impl From<Range<usize>> for SliceRange {
+    fn from(r: Range<usize>) -> Self {
+        SliceRange { start: r.start, end: r.end, step: 1 }
+    }
+}
+
+impl From<RangeFrom<usize>> for SliceRange {
+    fn from(r: RangeFrom<usize>) -> Self {
+        SliceRange { start: r.start, end: usize::MAX, step: 1 }
+    }
+}
+
+impl From<RangeTo<usize>> for SliceRange {
+    fn from(r: RangeTo<usize>) -> Self {
+        SliceRange { start: 0, end: r.end, step: 1 }
+    }
+}
+
+impl From<RangeInclusive<usize>> for SliceRange {
+    fn from(r: RangeInclusive<usize>) -> Self {
+        SliceRange { start: *r.start(), end: r.end().saturating_add(1), step: 1 }
+    }
+}
+
+impl From<RangeFull> for SliceRange {
+    fn from(_: RangeFull) -> Self {
+        SliceRange { start: 0, end: usize::MAX, step: 1 }
+    }
+}
+
 // TODO clean this up
 impl Debug for Tensor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -132,195 +226,1091 @@ impl Indexable for VecTensor {}
 
 impl From<&[f64]> for Tensor {
     fn from(data: &[f64]) -> Self {
-        Tensor {
-            data: data.to_vec(),
-            shape: vec![data.len()],
-            stride: vec![1],
-        }
+        Tensor::new(data.to_vec(), &[data.len()])
     }
 }
 
 impl From<f64> for Tensor {
     fn from(data: f64) -> Self {
-        Tensor {
-            data: vec![data],
-            shape: vec![],
-            stride: vec![1],
-        }
+        Tensor::new(vec![data], &[])
     }
 }
 
 impl Tensor {
+    /// a contiguous f64 tensor with `data` laid out row-major in `shape`
+    pub fn new(data: Vec<f64>, shape: &[usize]) -> Tensor {
+        Tensor::from_storage(Storage::F64(data), shape)
+    }
+
+    /// like `new`, for any element type, e.g. `Tensor::from_vec(vec![0i64, 2], &[2])`
+    pub fn from_vec<T: Element>(data: Vec<T>, shape: &[usize]) -> Tensor {
+        Tensor::from_storage(T::into_storage(data), shape)
+    }
+
+    /// `data` converted to `dtype`
+    pub(crate) fn new_as(data: Vec<f64>, shape: &[usize], dtype: DType) -> Tensor {
+        Tensor::from_storage(Storage::from_f64s(data, dtype), shape)
+    }
+
+    fn from_storage(data: Storage, shape: &[usize]) -> Tensor {
+        assert_eq!(
+            data.len(),
+            shape.iter().product::<usize>(),
+            "data of length {} doesn't fit shape {:?}",
+            data.len(),
+            shape
+        );
+        Tensor {
+            data: Rc::new(data),
+            offset: 0,
+            shape: shape.to_vec(),
+            stride: Tensor::get_postfix_prod(shape),
+        }
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.data.dtype()
+    }
+
+    /// A contiguous copy converted to `dtype` (see `Storage::from_f64s` for how),
+    /// or `self` if it's already that dtype.
+    pub fn to_dtype(&self, dtype: DType) -> Tensor {
+        if self.dtype() == dtype {
+            return self.clone();
+        }
+        Tensor::new_as(self.to_vec(), &self.shape, dtype)
+    }
+
     pub fn size(&self) -> &[usize] {
         &self.shape
     }
     pub fn n_elements(&self) -> usize {
-        self.data.len()
+        self.shape.iter().product()
     }
 
-    #[allow(dead_code)]
-    fn unsqueeze(&self, dim_index: usize) -> Tensor {
-        let mut shape = self.shape.clone();
-        let mut stride = self.stride.clone();
+    /// the elements in row-major order
+    pub fn to_vec(&self) -> Vec<f64> {
+        self.contiguous_data().into_owned()
+    }
 
-        shape.insert(dim_index, 0);
-        stride.insert(dim_index, 0);
+    /// whether `self` and `other` are views of the same buffer
+    pub(crate) fn shares_buffer(&self, other: &Tensor) -> bool {
+        Rc::ptr_eq(&self.data, &other.data)
+    }
 
-        Tensor {
+    /// `self` if it's already laid out row-major in its own stretch of the
+    /// buffer, otherwise a copy that is.
+    pub fn contiguous(&self) -> Tensor {
+        if self.is_contiguous() {
+            return self.clone();
+        }
+        Tensor::new_as(self.to_vec(), &self.shape, self.dtype())
+    }
+
+    /// A view with a new size-1 dimension inserted at `dim`. Negative `dim`
+    /// counts from the back of the *output*, so `-1` appends a dimension.
+    pub fn unsqueeze(&self, dim: isize) -> Tensor {
+        self.try_unsqueeze(dim).or_panic()
+    }
+
+    pub fn try_unsqueeze(&self, dim: isize) -> Result<Tensor, RaxError> {
+        let mut out = self.clone();
+        out.unsqueeze_(try_normalize_axis(dim, self.shape.len() + 1)?);
+        Ok(out)
+    }
+
+    /// A view without dimension `dim`, if it has size 1. Otherwise `self` is
+    /// returned unchanged.
+    pub fn squeeze(&self, dim: isize) -> Tensor {
+        self.try_squeeze(dim).or_panic()
+    }
+
+    pub fn try_squeeze(&self, dim: isize) -> Result<Tensor, RaxError> {
+        let dim = self.try_idx(dim)?;
+        let mut out = self.clone();
+        if self.shape[dim] == 1 {
+            out.squeeze_(dim);
+        }
+        Ok(out)
+    }
+
+    /// Merges dimensions `start..=end` into one. See `reshape` for when this copies.
+    pub fn flatten(&self, start: isize, end: isize) -> Tensor {
+        self.try_flatten(start, end).or_panic()
+    }
+
+    pub fn try_flatten(&self, start: isize, end: isize) -> Result<Tensor, RaxError> {
+        self.try_reshape(&self.flattened_shape(start, end)?)
+    }
+
+    pub(crate) fn flattened_shape(&self, start: isize, end: isize) -> Result<Vec<usize>, RaxError> {
+        if self.shape.is_empty() {
+            return Ok(vec![1]);
+        }
+        let (start, end) = (self.try_idx(start)?, self.try_idx(end)?);
+        if start > end {
+            return Err(RaxError::invalid("flatten", format!("start dim {start} is after end dim {end}")));
+        }
+
+        let mut shape = self.shape[..start].to_vec();
+        shape.push(self.shape[start..=end].iter().product());
+        shape.extend(&self.shape[end + 1..]);
+        Ok(shape)
+    }
+
+    /// A view with the dimensions reordered, so that dimension `i` of the
+    /// output is dimension `dims[i]` of `self`.
+    pub fn permute(&self, dims: &[isize]) -> Tensor {
+        self.try_permute(dims).or_panic()
+    }
+
+    pub fn try_permute(&self, dims: &[isize]) -> Result<Tensor, RaxError> {
+        let dims = dims.iter().map(|d| self.try_idx(*d)).collect::<Result<Vec<usize>, _>>()?;
+        let mut sorted = dims.clone();
+        sorted.sort();
+        if sorted != (0..self.shape.len()).collect::<Vec<usize>>() {
+            return Err(RaxError::shape("permute", &self.shape, &dims));
+        }
+
+        let mut out = self.clone();
+        out.shape = dims.iter().map(|d| self.shape[*d]).collect();
+        out.stride = dims.iter().map(|d| self.stride[*d]).collect();
+        Ok(out)
+    }
+
+    /// Like `reshape`, but never copies: panics if `self` isn't contiguous.
+    pub fn view(&self, shape: &[usize]) -> Tensor {
+        self.try_view(shape).or_panic()
+    }
+
+    /// a non-contiguous `self` is reported as a shape mismatch, as no shape
+    /// can view it
+    pub fn try_view(&self, shape: &[usize]) -> Result<Tensor, RaxError> {
+        if !self.is_contiguous() {
+            return Err(RaxError::shape("view (non-contiguous)", &self.shape, shape));
+        }
+        self.try_reshape(shape)
+    }
+
+    /// The same elements with a different shape. A view if `self` is
+    /// contiguous, otherwise the elements are copied first.
+    pub fn reshape(&self, shape: &[usize]) -> Tensor {
+        self.try_reshape(shape).or_panic()
+    }
+
+    pub fn try_reshape(&self, shape: &[usize]) -> Result<Tensor, RaxError> {
+        if self.n_elements() != shape.iter().product::<usize>() {
+            return Err(RaxError::shape("reshape", &self.shape, shape));
+        }
+        let mut out = self.contiguous();
+        out.shape = shape.to_vec();
+        out.stride = Tensor::get_postfix_prod(shape);
+        Ok(out)
+    }
+
+    /// A view of `self` broadcast to `shape`, without copying: broadcast
+    /// dimensions get a stride of 0.
+    pub fn expand(&self, shape: &[usize]) -> Tensor {
+        self.try_expand(shape).or_panic()
+    }
+
+    pub fn try_expand(&self, shape: &[usize]) -> Result<Tensor, RaxError> {
+        if broadcast_shapes(&self.shape, shape).as_deref() != Some(shape) {
+            return Err(RaxError::shape("expand", &self.shape, shape));
+        }
+        Ok(Tensor {
             data: self.data.clone(),
-            stride,
-            shape,
+            offset: self.offset,
+            shape: shape.to_vec(),
+            stride: self.broadcast_stride(shape),
+        })
+    }
+
+    /// A view of elements `start..start + len` along `dim`.
+    pub fn narrow(&self, dim: isize, start: usize, len: usize) -> Tensor {
+        let dim = self.idx(dim);
+        assert!(
+            start + len <= self.shape[dim],
+            "can't take {}..{} of dimension {} with size {}",
+            start,
+            start + len,
+            dim,
+            self.shape[dim]
+        );
+        let mut out = self.clone();
+        out.offset += start * self.stride[dim];
+        out.shape[dim] = len;
+        out
+    }
+
+    /// A view of `self` sliced along its leading dimensions, like python's
+    /// `x[a:b:s, c:d]`. Dimensions past the end of `ranges` are kept whole, and
+    /// ends past the size of a dimension are clamped to it.
+    pub fn slice(&self, ranges: &[SliceRange]) -> Tensor {
+        self.try_slice(ranges).or_panic()
+    }
+
+    /// errors if there are more `ranges` than dimensions
+    pub fn try_slice(&self, ranges: &[SliceRange]) -> Result<Tensor, RaxError> {
+        if ranges.len() > self.shape.len() {
+            return Err(RaxError::shape("slice", &self.shape, &vec![0; ranges.len()]));
+        }
+        let mut out = self.clone();
+        for (dim, range) in ranges.iter().enumerate() {
+            let (start, len) = range.resolve(self.shape[dim]);
+            out.offset += start * self.stride[dim];
+            out.shape[dim] = len;
+            out.stride[dim] *= range.step;
+        }
+        Ok(out)
+    }
+
+    /// A copy of `self` with the region selected by `ranges` replaced by `src`,
+    /// which is broadcast to the region's shape. The inverse of `slice`.
+    pub fn slice_scatter(&self, ranges: &[SliceRange], src: &Tensor) -> Tensor {
+        let region = self.slice(ranges);
+        let values = src.expand(&region.shape).to_vec();
+
+        // `region` is a view into a fresh copy of `self`, so its offsets index that copy
+        let mut out = Tensor::new_as(self.to_vec(), &self.shape, self.dtype());
+        let offsets: Vec<usize> = out.slice(ranges).strided_offsets().collect();
+        let data = Rc::make_mut(&mut out.data);
+        for (offset, v) in zip(offsets, values) {
+            data.set(offset, v);
+        }
+        out
+    }
+
+    /// The entries at `indices` along `dim`, in that order. Indices may repeat.
+    pub fn index_select(&self, dim: isize, indices: &[usize]) -> Tensor {
+        self.try_index_select(dim, indices).or_panic()
+    }
+
+    pub fn try_index_select(&self, dim: isize, indices: &[usize]) -> Result<Tensor, RaxError> {
+        let dim = self.try_idx(dim)?;
+        let mut shape = self.shape.clone();
+        shape[dim] = indices.len();
+
+        let mut out = VecTensor::zeroes(&shape);
+        try_for_each_index(&shape, |idx| {
+            let mut src_idx = idx.clone();
+            src_idx[dim] = indices[idx[dim]];
+            *out.at_mut(idx) = self.at(&src_idx)?;
+            Ok(())
+        })?;
+        Ok(out.into_tensor_as(self.dtype()))
+    }
+
+    /// A copy of `self` with each entry `i` of `src` along `dim` added into
+    /// entry `indices[i]`. The inverse of `index_select`: repeated indices accumulate.
+    pub fn index_add(&self, dim: isize, indices: &[usize], src: &Tensor) -> Tensor {
+        let dim = self.idx(dim);
+        assert_eq!(src.shape[dim], indices.len());
+
+        let mut out = VecTensor::from_tensor(self);
+        for_each_index(&src.shape, |idx| {
+            let mut dest_idx = idx.clone();
+            dest_idx[dim] = indices[idx[dim]];
+            *out.at_mut(&dest_idx) += src.at(idx).unwrap();
+        });
+        out.into_tensor_as(self.dtype())
+    }
+
+    /// `out[i][j] = self[index[i][j]][j]` for `dim = 0`, and similarly for other
+    /// dimensions. `index` is an I64 tensor with the same number of dimensions
+    /// as `self`. The output has the shape of `index`.
+    pub fn gather(&self, dim: isize, index: &Tensor) -> Tensor {
+        self.try_gather(dim, index).or_panic()
+    }
+
+    /// errors on an `index` that isn't I64 (a bool one is almost certainly a
+    /// mask meant for `masked_select`) or has negative entries
+    pub fn try_gather(&self, dim: isize, index: &Tensor) -> Result<Tensor, RaxError> {
+        let dim = self.try_idx(dim)?;
+        check_index_tensor("gather", index)?;
+        if index.shape.len() != self.shape.len() {
+            return Err(RaxError::shape("gather", &self.shape, &index.shape));
+        }
+
+        let mut out = VecTensor::zeroes(&index.shape);
+        try_for_each_index(&index.shape, |idx| {
+            let mut src_idx = idx.clone();
+            src_idx[dim] = index_position("gather", index.at(idx)?)?;
+            *out.at_mut(idx) = self.at(&src_idx)?;
+            Ok(())
+        })?;
+        Ok(out.into_tensor_as(self.dtype()))
+    }
+
+    /// A copy of `self` with `src[i][j]` added into `self[index[i][j]][j]` for
+    /// `dim = 0`, and similarly for other dimensions. The inverse of `gather`.
+    pub fn scatter_add(&self, dim: isize, index: &Tensor, src: &Tensor) -> Tensor {
+        self.try_scatter_add(dim, index, src).or_panic()
+    }
+
+    pub fn try_scatter_add(&self, dim: isize, index: &Tensor, src: &Tensor) -> Result<Tensor, RaxError> {
+        let dim = self.try_idx(dim)?;
+        check_index_tensor("scatter_add", index)?;
+        if index.shape != src.shape {
+            return Err(RaxError::shape("scatter_add", &index.shape, &src.shape));
+        }
+        if index.shape.len() != self.shape.len() {
+            return Err(RaxError::shape("scatter_add", &self.shape, &index.shape));
+        }
+
+        let mut out = VecTensor::from_tensor(self);
+        try_for_each_index(&index.shape, |idx| {
+            let mut dest_idx = idx.clone();
+            dest_idx[dim] = index_position("scatter_add", index.at(idx)?)?;
+            self.check_index(&dest_idx)?;
+            *out.at_mut(&dest_idx) += src.at(idx)?;
+            Ok(())
+        })?;
+        Ok(out.into_tensor_as(self.dtype()))
+    }
+
+    /// The elements where `mask` (broadcast to `self`) is non-zero, as a 1-D
+    /// tensor in row-major order.
+    pub fn masked_select(&self, mask: &Tensor) -> Tensor {
+        self.try_masked_select(mask).or_panic()
+    }
+
+    pub fn try_masked_select(&self, mask: &Tensor) -> Result<Tensor, RaxError> {
+        let mask = mask
+            .try_expand(&self.shape)
+            .map_err(|_| RaxError::shape("masked_select", &self.shape, &mask.shape))?
+            .to_vec();
+        let data: Vec<f64> = zip(self.to_vec(), mask)
+            .filter(|(_, m)| *m != 0.)
+            .map(|(v, _)| v)
+            .collect();
+        let len = data.len();
+        Ok(Tensor::new_as(data, &[len], self.dtype()))
+    }
+
+    /// A copy of `self` with the positions where `mask` is non-zero filled, in
+    /// row-major order, from the 1-D `src`. The inverse of `masked_select`.
+    pub fn masked_scatter(&self, mask: &Tensor, src: &Tensor) -> Tensor {
+        let mask = mask.expand(&self.shape).to_vec();
+        let mut src = src.to_vec().into_iter();
+        let data = zip(self.to_vec(), mask)
+            .map(|(v, m)| if m != 0. { src.next().expect("not enough values to scatter") } else { v })
+            .collect();
+        Tensor::new(data, &self.shape)
+    }
+
+    /// Joins `tensors` along the existing dimension `dim`. Every other dimension
+    /// must match.
+    pub fn cat(tensors: &[&Tensor], dim: isize) -> Tensor {
+        Tensor::try_cat(tensors, dim).or_panic()
+    }
+
+    /// errors if `tensors` is empty, reported as a mismatch between two empty shapes
+    pub fn try_cat(tensors: &[&Tensor], dim: isize) -> Result<Tensor, RaxError> {
+        let Some(first) = tensors.first() else {
+            return Err(RaxError::shape("cat", &[], &[]));
+        };
+        let dim = first.try_idx(dim)?;
+
+        let mut shape = first.shape.clone();
+        shape[dim] = 0;
+        for t in tensors {
+            let fits = t.shape.len() == first.shape.len()
+                && zip(&t.shape, &first.shape)
+                    .enumerate()
+                    .all(|(d, (a, b))| d == dim || a == b);
+            if !fits {
+                return Err(RaxError::shape("cat", &first.shape, &t.shape));
+            }
+            shape[dim] += t.shape[dim];
+        }
+
+        let mut out = VecTensor::zeroes(&shape);
+        let mut start = 0;
+        for t in tensors {
+            for_each_index(&t.shape, |idx| {
+                let mut dest_idx = idx.clone();
+                dest_idx[dim] += start;
+                *out.at_mut(&dest_idx) = t.at(idx).unwrap();
+            });
+            start += t.shape[dim];
+        }
+        let dtype = tensors.iter().map(|t| t.dtype()).max().unwrap();
+        Ok(out.into_tensor_as(dtype))
+    }
+
+    /// Joins same-shaped `tensors` along a new dimension inserted at `dim`.
+    pub fn stack(tensors: &[&Tensor], dim: isize) -> Tensor {
+        Tensor::try_stack(tensors, dim).or_panic()
+    }
+
+    pub fn try_stack(tensors: &[&Tensor], dim: isize) -> Result<Tensor, RaxError> {
+        let Some(first) = tensors.first() else {
+            return Err(RaxError::shape("stack", &[], &[]));
+        };
+        if let Some(t) = tensors.iter().find(|t| t.shape != first.shape) {
+            return Err(RaxError::shape("stack", &first.shape, &t.shape));
+        }
+        let unsqueezed = tensors.iter().map(|t| t.try_unsqueeze(dim)).collect::<Result<Vec<Tensor>, _>>()?;
+        let dim = normalize_axis(dim, first.shape.len() + 1) as isize;
+        Tensor::try_cat(&unsqueezed.iter().collect::<Vec<&Tensor>>(), dim)
+    }
+
+    /// Views of consecutive pieces of `split_size` along `dim`. The last piece
+    /// is smaller if the dimension doesn't divide evenly.
+    pub fn split(&self, split_size: usize, dim: isize) -> Vec<Tensor> {
+        self.try_split(split_size, dim).or_panic()
+    }
+
+    /// errors if `split_size` is 0
+    pub fn try_split(&self, split_size: usize, dim: isize) -> Result<Vec<Tensor>, RaxError> {
+        if split_size == 0 {
+            return Err(RaxError::invalid("split", "split size must be positive"));
+        }
+        let dim_size = self.shape[self.try_idx(dim)?];
+        Ok((0..dim_size)
+            .step_by(split_size)
+            .map(|start| self.narrow(dim, start, split_size.min(dim_size - start)))
+            .collect())
+    }
+
+    /// Splits into (at most) `n_chunks` views of equal size along `dim`, the
+    /// last one smaller if the dimension doesn't divide evenly.
+    pub fn chunk(&self, n_chunks: usize, dim: isize) -> Vec<Tensor> {
+        self.try_chunk(n_chunks, dim).or_panic()
+    }
+
+    /// errors if `n_chunks` is 0
+    pub fn try_chunk(&self, n_chunks: usize, dim: isize) -> Result<Vec<Tensor>, RaxError> {
+        if n_chunks == 0 {
+            return Err(RaxError::invalid("chunk", "number of chunks must be positive"));
+        }
+        let dim_size = self.shape[self.try_idx(dim)?];
+        self.try_split(dim_size.div_ceil(n_chunks).max(1), dim)
+    }
+
+    pub(crate) fn unsqueeze_(&mut self, dim_index: usize) {
+        self.shape.insert(dim_index, 1);
+        self.stride.insert(dim_index, 1);
+    }
+
+    pub(crate) fn squeeze_(&mut self, dim_index: usize) {
+        assert_eq!(self.shape[dim_index], 1, "can only squeeze a dimension of size 1");
+        self.shape.remove(dim_index);
+        self.stride.remove(dim_index);
+    }
+
+    /// the only element of a tensor with one element, of any shape
+    pub fn item(&self) -> Result<f64, RaxError> {
+        if self.n_elements() == 1 {
+            Ok(self.data.get(self.offset))
+        } else {
+            Err(RaxError::shape("item", &self.shape, &[]))
+        }
+    }
+
+    fn get_postfix_prod(shape: &[usize]) -> Vec<usize> {
+        let l = shape.len();
+        let mut out: Vec<usize> = vec![0; l];
+        let mut n = 1;
+        for i in (0..l).rev() {
+            out[i] = n;
+            n *= shape[i];
+        }
+        out
+    }
+
+    /// uniform on [0, 1), from the thread's global generator. see `random`
+    /// for seeding and other distributions.
+    pub fn rand(shape: &[usize]) -> Tensor {
+        random::rand(shape)
+    }
+
+    pub fn at(&self, indices: &Vec<usize>) -> Result<f64, RaxError> {
+        self.check_index(indices)?;
+        Ok(self.data.get(self.offset + self.flat_idx(indices)))
+    }
+
+    /// errors unless `indices` has one in-bounds index per dimension
+    fn check_index(&self, indices: &[usize]) -> Result<(), RaxError> {
+        if indices.len() != self.shape.len() || zip(indices, &self.shape).any(|(idx, dim)| idx >= dim) {
+            return Err(RaxError::IndexOutOfBounds {
+                index: indices.to_vec(),
+                shape: self.shape.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Matrix product with numpy `matmul` semantics:
+    /// - a 1-D left argument is treated as a row vector `[1, k]`, and a 1-D right
+    ///   argument as a column vector `[k, 1]`. the added dimension is removed
+    ///   from the result.
+    /// - any dimensions before the last two are batch dimensions, which broadcast.
+    pub fn matmul(&self, rhs: &Self) -> Tensor {
+        self.try_matmul(rhs).or_panic()
+    }
+
+    pub fn try_matmul(&self, rhs: &Self) -> Result<Tensor, RaxError> {
+        self.promoted_matmul(rhs, &Tensor::batched_matmul)
+    }
+
+    /// The original element-at-a-time matmul, kept as a reference to test and
+    /// benchmark the blocked kernel behind `matmul` against.
+    pub fn matmul_naive(&self, rhs: &Self) -> Tensor {
+        self.promoted_matmul(rhs, &Tensor::batched_matmul_naive).or_panic()
+    }
+
+    fn promoted_matmul(
+        &self,
+        rhs: &Self,
+        batched: &impl Fn(&Tensor, &Tensor) -> Tensor,
+    ) -> Result<Tensor, RaxError> {
+        // 0-d arguments, mismatched inner dimensions, and batch dimensions that don't broadcast
+        let mismatch = || RaxError::shape("matmul", &self.shape, &rhs.shape);
+        if self.shape.is_empty() || rhs.shape.is_empty() {
+            return Err(mismatch());
+        }
+
+        let (l, r) = (self.promote_matmul_lhs(), rhs.promote_matmul_rhs());
+        let (l_nd, r_nd) = (l.shape.len(), r.shape.len());
+        if l.shape[l_nd - 1] != r.shape[r_nd - 2] || broadcast_shapes(&l.shape[..l_nd - 2], &r.shape[..r_nd - 2]).is_none() {
+            return Err(mismatch());
+        }
+        let mut out = batched(&l, &r);
+
+        if self.shape.len() == 1 {
+            out.squeeze_(out.shape.len() - 2);
+        }
+        if rhs.shape.len() == 1 {
+            out.squeeze_(out.shape.len() - 1);
+        }
+        Ok(out.to_dtype(result_dtype(self, rhs).to_arith()))
+    }
+
+    /// 1-D tensors become a `[1, k]` row vector on the left of a matmul
+    pub(crate) fn promote_matmul_lhs(&self) -> Tensor {
+        let mut out = self.clone();
+        if out.shape.len() == 1 {
+            out.unsqueeze_(0);
+        }
+        out
+    }
+
+    /// 1-D tensors become a `[k, 1]` column vector on the right of a matmul
+    pub(crate) fn promote_matmul_rhs(&self) -> Tensor {
+        let mut out = self.clone();
+        if out.shape.len() == 1 {
+            out.unsqueeze_(1);
+        }
+        out
+    }
+
+    /// matmul over the last two dimensions of two (at least) 2-D tensors,
+    /// broadcasting the leading batch dimensions.
+    ///
+    /// Both arguments are laid out row-major (a no-op unless they're strided
+    /// views) and each batch is handed to `matmul_kernel` as plain slices.
+    pub(crate) fn batched_matmul(&self, rhs: &Self) -> Tensor {
+        let dims = MatmulDims::new(self, rhs);
+        let (h, k, w) = (dims.h, dims.inner, dims.w);
+
+        let mut out = VecTensor::zeroes(&dims.out_shape());
+        if out.data.is_empty() {
+            return out.into_tensor();
+        }
+
+        let (l_data, r_data) = (self.contiguous_data(), rhs.contiguous_data());
+        let l_batch_stride = Tensor::get_postfix_prod(&dims.l_batch);
+        let r_batch_stride = Tensor::get_postfix_prod(&dims.r_batch);
+
+        // batches are visited in row-major order, the same order as the output chunks
+        let mut out_batches = out.data.chunks_mut(h * w);
+        for_each_index(&dims.batch_shape, |batch_idx| {
+            let l_offset = flat_offset(&broadcast_index(batch_idx, &dims.l_batch), &l_batch_stride) * h * k;
+            let r_offset = flat_offset(&broadcast_index(batch_idx, &dims.r_batch), &r_batch_stride) * k * w;
+            matmul_kernel(
+                &l_data[l_offset..l_offset + h * k],
+                &r_data[r_offset..r_offset + k * w],
+                out_batches.next().unwrap(),
+                k,
+                w,
+            );
+        });
+
+        out.into_tensor()
+    }
+
+    fn batched_matmul_naive(&self, rhs: &Self) -> Tensor {
+        let dims = MatmulDims::new(self, rhs);
+        let (l_nd, r_nd) = (self.shape.len(), rhs.shape.len());
+        let out_nd = dims.batch_shape.len() + 2;
+
+        let mut out = VecTensor::zeroes(&dims.out_shape());
+
+        for_each_index(&dims.batch_shape, |batch_idx| {
+            let mut l_idx = broadcast_index(batch_idx, &dims.l_batch);
+            let mut r_idx = broadcast_index(batch_idx, &dims.r_batch);
+            let mut out_idx = batch_idx.clone();
+            l_idx.extend([0, 0]);
+            r_idx.extend([0, 0]);
+            out_idx.extend([0, 0]);
+
+            for i in 0..dims.h {
+                for j in 0..dims.w {
+                    let mut sum: f64 = 0.;
+                    for k in 0..dims.inner {
+                        (l_idx[l_nd - 2], l_idx[l_nd - 1]) = (i, k);
+                        (r_idx[r_nd - 2], r_idx[r_nd - 1]) = (k, j);
+                        sum += self.at(&l_idx).unwrap() * rhs.at(&r_idx).unwrap();
+                    }
+                    (out_idx[out_nd - 2], out_idx[out_nd - 1]) = (i, j);
+                    *out.at_mut(&out_idx) = sum;
+                }
+            }
+        });
+
+        out.into_tensor()
+    }
+
+    /// The elements in row-major order. Borrows the buffer if it's already laid
+    /// out that way, otherwise gathers a copy.
+    pub(crate) fn contiguous_data(&self) -> Cow<'_, [f64]> {
+        if let (true, Some(data)) = (self.is_contiguous(), self.data.as_f64s()) {
+            return Cow::Borrowed(&data[self.offset..self.offset + self.n_elements()]);
+        }
+        Cow::Owned(self.strided_offsets().map(|i| self.data.get(i)).collect())
+    }
+
+    /// offsets into `data` of every element, in row-major order
+    fn strided_offsets(&self) -> StridedOffsets {
+        StridedOffsets::new(&self.shape, self.stride.clone(), self.offset)
+    }
+
+    /// The stride to walk `self` with as though it had been broadcast to
+    /// `shape`: right-aligned, and 0 for every dimension being broadcast.
+    fn broadcast_stride(&self, shape: &[usize]) -> Vec<usize> {
+        let n_leading = shape.len() - self.shape.len();
+        let mut out = vec![0; n_leading];
+        out.extend(
+            zip(&self.shape, &self.stride).map(|(dim, stride)| if *dim == 1 { 0 } else { *stride }),
+        );
+        out
+    }
+
+    /// whether the buffer is exactly the elements in row-major order
+    pub(crate) fn is_contiguous(&self) -> bool {
+        // the stride of a size-1 dimension never matters
+        zip(zip(&self.shape, &self.stride), Tensor::get_postfix_prod(&self.shape))
+            .all(|((dim, stride), expected)| *dim == 1 || *stride == expected)
+    }
+
+    pub fn transpose(&self, dim_idx_1: isize, dim_idx_2: isize) -> Tensor {
+        let mut new_t = self.clone();
+        let dim_idx_1 = self.idx(dim_idx_1);
+        let dim_idx_2 = self.idx(dim_idx_2);
+
+        new_t.shape.swap(dim_idx_1, dim_idx_2);
+        new_t.stride.swap(dim_idx_1, dim_idx_2);
+
+        new_t
+    }
+
+    fn idx(&self, idx: isize) -> usize {
+        self.try_idx(idx).or_panic()
+    }
+
+    /// `idx` as a dimension of `self`, counting from the back if negative
+    pub(crate) fn try_idx(&self, idx: isize) -> Result<usize, RaxError> {
+        try_normalize_axis(idx, self.shape.len())
+    }
+
+    pub fn zeros(shape: &[usize]) -> Tensor {
+        VecTensor::zeroes(shape).into_tensor()
+    }
+
+    pub fn add(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map("add", l, r, &|l, r| l + r)
+    }
+
+    pub fn mul(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map("mul", l, r, &|l, r| l * r)
+    }
+
+    pub fn sub(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map("sub", l, r, &|l, r| l - r)
+    }
+
+    /// true division: ints and bools give f64
+    pub fn div(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map_as("div", l, r, result_dtype(l, r).to_float(), &|l, r| l / r)
+    }
+
+    /// elementwise max of two tensors. not to be confused with `max`, which reduces
+    pub fn maximum(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map("maximum", l, r, &f64::max)
+    }
+
+    /// elementwise min of two tensors, see `maximum`
+    pub fn minimum(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map("minimum", l, r, &f64::min)
+    }
+
+    /// `l^r` elementwise, for a tensor of exponents. see `pow` for a scalar one.
+    pub fn pow_tensor(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_broadcasted_map_as("pow", l, r, result_dtype(l, r).to_float(), &f64::powf)
+    }
+
+    pub fn sqr(t: &Tensor) -> Result<Tensor, RaxError> {
+        elementwise_map_as(t, t.dtype().to_arith(), &|a| a * a)
+    }
+
+    pub fn exp(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::exp).unwrap()
+    }
+
+    /// natural log
+    pub fn log(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::ln).unwrap()
+    }
+
+    pub fn sqrt(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::sqrt).unwrap()
+    }
+
+    /// 1 / sqrt(t)
+    pub fn rsqrt(t: &Tensor) -> Tensor {
+        elementwise_map(t, &|a| 1. / a.sqrt()).unwrap()
+    }
+
+    /// every element raised to the scalar power `p`
+    pub fn pow(t: &Tensor, p: f64) -> Tensor {
+        elementwise_map(t, &|a| a.powf(p)).unwrap()
+    }
+
+    pub fn abs(t: &Tensor) -> Tensor {
+        elementwise_map_as(t, t.dtype().to_arith(), &f64::abs).unwrap()
+    }
+
+    /// -1, 0 or 1 depending on the sign of each element
+    pub fn sign(t: &Tensor) -> Tensor {
+        elementwise_map_as(t, t.dtype().to_arith(), &|a| if a > 0. { 1. } else if a < 0. { -1. } else { 0. }).unwrap()
+    }
+
+    pub fn sin(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::sin).unwrap()
+    }
+
+    pub fn cos(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::cos).unwrap()
+    }
+
+    /// 1 / t
+    pub fn reciprocal(t: &Tensor) -> Tensor {
+        elementwise_map(t, &|a| 1. / a).unwrap()
+    }
+
+    /// 1 / (1 + e^-x). only ever exponentiates a non-positive number, so it
+    /// doesn't overflow for large |x|
+    pub fn sigmoid(t: &Tensor) -> Tensor {
+        elementwise_map(t, &sigmoid).unwrap()
+    }
+
+    pub fn tanh(t: &Tensor) -> Tensor {
+        elementwise_map(t, &f64::tanh).unwrap()
+    }
+
+    /// GELU, using the tanh approximation
+    /// 0.5 x (1 + tanh(sqrt(2/π) (x + 0.044715 x^3)))
+    pub fn gelu(t: &Tensor) -> Tensor {
+        elementwise_map(t, &|a| 0.5 * a * (1. + gelu_inner(a).tanh())).unwrap()
+    }
+
+    /// x * sigmoid(x), a.k.a. swish
+    pub fn silu(t: &Tensor) -> Tensor {
+        elementwise_map(t, &|a| a * sigmoid(a)).unwrap()
+    }
+
+    /// x where x > 0, `slope * x` elsewhere
+    pub fn leaky_relu(t: &Tensor, slope: f64) -> Tensor {
+        elementwise_map(t, &|a| if a > 0. { a } else { slope * a }).unwrap()
+    }
+
+    /// x where x > 0, `alpha * (e^x - 1)` elsewhere
+    pub fn elu(t: &Tensor, alpha: f64) -> Tensor {
+        elementwise_map(t, &|a| if a > 0. { a } else { alpha * a.exp_m1() }).unwrap()
+    }
+
+    /// ln(1 + e^x), computed as max(x, 0) + ln(1 + e^-|x|) so it doesn't overflow
+    pub fn softplus(t: &Tensor) -> Tensor {
+        elementwise_map(t, &|a| a.max(0.) + (-a.abs()).exp().ln_1p()).unwrap()
+    }
+
+    /// Sum over `axes` (negative indices count from the back). An empty `axes`
+    /// reduces over every dimension. With `keepdim` the reduced dimensions are
+    /// kept with size 1, otherwise they're removed.
+    pub fn sum(&self, axes: &[isize], keepdim: bool) -> Tensor {
+        self.reduce(axes, keepdim, 0., &|acc, v| acc + v)
+    }
+
+    pub fn mean(&self, axes: &[isize], keepdim: bool) -> Tensor {
+        let n = self.n_reduced(axes) as f64;
+        let sum = self.sum(axes, keepdim);
+        elementwise_map(&sum, &|v| v / n).unwrap()
+    }
+
+    pub fn max(&self, axes: &[isize], keepdim: bool) -> Tensor {
+        self.reduce(axes, keepdim, f64::NEG_INFINITY, &|acc, v| if v > acc { v } else { acc })
+    }
+
+    pub fn min(&self, axes: &[isize], keepdim: bool) -> Tensor {
+        self.reduce(axes, keepdim, f64::INFINITY, &|acc, v| if v < acc { v } else { acc })
+    }
+
+    pub fn prod(&self, axes: &[isize], keepdim: bool) -> Tensor {
+        self.reduce(axes, keepdim, 1., &|acc, v| acc * v)
+    }
+
+    /// ln(sum(e^x)) along `dim`, computed as m + ln(sum(e^(x - m))) with m the
+    /// max of the slice, so large values don't overflow
+    pub fn logsumexp(&self, dim: isize, keepdim: bool) -> Tensor {
+        // a slice of all -inf has max -inf, and -inf - -inf is NaN
+        let m = elementwise_map(&self.max(&[dim], true), &|m| if m.is_finite() { m } else { 0. }).unwrap();
+        let shifted = Tensor::exp(&Tensor::sub(self, &m).unwrap());
+        let lse = Tensor::add(&Tensor::log(&shifted.sum(&[dim], true)), &m).unwrap();
+        if keepdim {
+            lse
+        } else {
+            lse.squeeze(dim)
         }
     }
 
-    fn unsqueeze_(&mut self, dim_index: usize) {
-        self.shape.insert(dim_index, 1);
-        self.stride.insert(dim_index, 1);
+    /// e^x / sum(e^x) along `dim`
+    pub fn softmax(&self, dim: isize) -> Tensor {
+        Tensor::exp(&self.log_softmax(dim))
+    }
+
+    /// x - logsumexp(x) along `dim`
+    pub fn log_softmax(&self, dim: isize) -> Tensor {
+        Tensor::sub(self, &self.logsumexp(dim, true)).unwrap()
+    }
+
+    /// A mask of 1s and 0s with 1 at the position of the maximum of each slice
+    /// reduced over `axes`. Ties go to the first occurrence, so exactly one
+    /// element per slice is set.
+    pub fn argmax_mask(&self, axes: &[isize]) -> Tensor {
+        self.extremum_mask(axes, &|v, best| v > best)
+    }
+
+    /// see `argmax_mask`
+    pub fn argmin_mask(&self, axes: &[isize]) -> Tensor {
+        self.extremum_mask(axes, &|v, best| v < best)
     }
 
-    pub fn item(&self) -> Result<f64, String> {
-        if self.n_elements() == 1 {
-            Ok(self.data[0])
-        } else {
-            Err(format!(
-                "Can only call `item` on a singleton tensor, got {:?}",
-                self.shape
-            ))
-        }
+    /// For each element, the product of every *other* element in its slice
+    /// reduced over `axes`. This is the gradient of `prod`, computed without
+    /// dividing by the element so that zeros are handled correctly.
+    pub fn prod_others(&self, axes: &[isize]) -> Tensor {
+        let axes = self.reduce_axes(axes);
+
+        // per slice: product of the non-zero elements, and how many zeros there are
+        let mut nonzero_prod = VecTensor::ones(&self.reduced_shape(&axes, true));
+        let mut n_zeros = VecTensor::zeroes(&self.reduced_shape(&axes, true));
+        for_each_index(&self.shape, |idx| {
+            let v = self.at(idx).unwrap();
+            let out_idx = reduced_index(idx, &axes, true);
+            if v == 0. {
+                *n_zeros.at_mut(&out_idx) += 1.;
+            } else {
+                *nonzero_prod.at_mut(&out_idx) *= v;
+            }
+        });
+
+        let (nonzero_prod, n_zeros) = (nonzero_prod.into_tensor(), n_zeros.into_tensor());
+        let mut out = VecTensor::zeroes(&self.shape);
+        for_each_index(&self.shape, |idx| {
+            let v = self.at(idx).unwrap();
+            let out_idx = reduced_index(idx, &axes, true);
+            let p = nonzero_prod.at(&out_idx).unwrap();
+            *out.at_mut(idx) = match n_zeros.at(&out_idx).unwrap() as usize {
+                0 => p / v,
+                1 if v == 0. => p,
+                _ => 0.,
+            };
+        });
+        out.into_tensor()
     }
 
-    fn get_postfix_prod(shape: &[usize]) -> Vec<usize> {
-        let l = shape.len();
-        let mut out: Vec<usize> = vec![0; l];
-        let mut n = 1;
-        for i in (0..l).rev() {
-            out[i] = n;
-            n *= shape[i];
+    /// Reshape a tensor reduced over `axes` with `keepdim = false` back to the
+    /// `keepdim = true` shape, so it broadcasts against the unreduced tensor.
+    pub fn unsqueeze_reduced(&self, axes: &[isize], n_dims: usize) -> Tensor {
+        let mut out = self.clone();
+        if out.shape.len() == n_dims {
+            return out;
+        }
+        for axis in normalize_axes(axes, n_dims) {
+            out.unsqueeze_(axis);
         }
         out
     }
 
-    pub fn rand(shape: &[usize]) -> Tensor {
-        let mut rng = rand::thread_rng();
-        let mut base = VecTensor::zeroes(shape);
-        for n in base.data.iter_mut() {
-            *n = rng.gen();
-        }
-        base.as_tensor()
+    /// number of elements folded into each output element when reducing over `axes`
+    pub fn n_reduced(&self, axes: &[isize]) -> usize {
+        self.reduce_axes(axes).iter().map(|a| self.shape[*a]).product()
     }
 
-    pub fn at(&self, indices: &Vec<usize>) -> Result<f64, String> {
-        assert!(indices.len() == self.shape.len());
-        let idx = self.flat_idx(indices);
-        if let Some(val) = self.data.get(idx) {
-            return Ok(*val);
-        }
-        Err(format!(
-            "Index out of bounds. indices: {:?}, shape: {:?}",
-            indices, self.shape
-        ))
+    fn reduce_axes(&self, axes: &[isize]) -> Vec<usize> {
+        normalize_axes(axes, self.shape.len())
     }
 
-    // todo extend to arbitrary dimensions
-    pub fn matmul(&self, rhs: &Self) -> Tensor {
-        assert!(self.shape.len() == 2 && rhs.shape.len() == 2);
-        assert!(self.shape[1] == rhs.shape[0]);
-
-        // println!("matmul: {:?} {:?}", self.shape, rhs.shape);
-
-        let h = self.shape[0];
-        let w = rhs.shape[1];
-        let inner_dim = self.shape[1];
-        let mut out = VecTensor::zeroes(&[h, w]);
-        for i in 0..h {
-            for j in 0..w {
-                let mut sum: f64 = 0.;
-                for k in 0..inner_dim {
-                    let l = self.at(&vec![i, k]).unwrap();
-                    let r = rhs.at(&vec![k, j]).unwrap();
-                    sum += l * r;
-                }
-                *out.at_mut(&vec![i, j]) = sum;
+    fn reduced_shape(&self, axes: &[usize], keepdim: bool) -> Vec<usize> {
+        let mut shape = vec![];
+        for (i, dim) in self.shape.iter().enumerate() {
+            if !axes.contains(&i) {
+                shape.push(*dim);
+            } else if keepdim {
+                shape.push(1);
             }
         }
-        out.as_tensor()
+        shape
     }
 
-    pub fn transpose(&self, dim_idx_1: isize, dim_idx_2: isize) -> Tensor {
-        let mut new_t = self.clone();
-        let dim_idx_1 = self.idx(dim_idx_1);
-        let dim_idx_2 = self.idx(dim_idx_2);
+    fn reduce(&self, axes: &[isize], keepdim: bool, init: f64, func: &impl Fn(f64, f64) -> f64) -> Tensor {
+        let axes = self.reduce_axes(axes);
+        let mut out = VecTensor::full(&self.reduced_shape(&axes, keepdim), init);
+        for_each_index(&self.shape, |idx| {
+            let acc = out.at_mut(&reduced_index(idx, &axes, keepdim));
+            *acc = func(*acc, self.at(idx).unwrap());
+        });
+        out.into_tensor_as(self.dtype().to_arith())
+    }
 
-        new_t.shape.swap(dim_idx_1, dim_idx_2);
-        new_t.stride.swap(dim_idx_1, dim_idx_2);
+    fn extremum_mask(&self, axes: &[isize], is_better: &impl Fn(f64, f64) -> bool) -> Tensor {
+        let axes = self.reduce_axes(axes);
+
+        // the best value seen so far per slice, and where it was
+        let reduced_shape = self.reduced_shape(&axes, true);
+        let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; reduced_shape.iter().product()];
+        let reduced_stride = Tensor::get_postfix_prod(&reduced_shape);
+
+        for_each_index(&self.shape, |idx| {
+            let v = self.at(idx).unwrap();
+            let slot: usize = zip(reduced_index(idx, &axes, true), &reduced_stride)
+                .map(|(i, s)| i * s)
+                .sum();
+            match &best[slot] {
+                Some((b, _)) if !is_better(v, *b) => {}
+                _ => best[slot] = Some((v, idx.clone())),
+            }
+        });
 
-        new_t
+        let mut out = VecTensor::zeroes(&self.shape);
+        for (_, idx) in best.into_iter().flatten() {
+            *out.at_mut(&idx) = 1.;
+        }
+        out.into_tensor_as(DType::Bool)
     }
 
-    fn idx(&self, idx: isize) -> usize {
-        if idx < 0 {
-            self.shape.len() - 1 + idx.unsigned_abs()
-        } else {
-            idx as usize
-        }
+    pub fn relu(t: &Tensor) -> Tensor {
+        Self::mul(&Self::gt(t, &Tensor::from(0.)).unwrap(), t).unwrap()
     }
 
-    pub fn zeros(shape: &[usize]) -> Tensor {
-        let capacity = shape.iter().product();
-        Tensor {
-            data: vec![0.; capacity],
-            shape: shape.to_vec(),
-            stride: Tensor::get_postfix_prod(shape),
-        }
+    // COMPARISONS
+    // these broadcast like the arithmetic ops, and give a mask of 1s where the
+    // comparison holds and 0s elsewhere
+
+    pub fn eq(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("eq", l, r, &|l, r| l == r)
+    }
+
+    pub fn ne(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("ne", l, r, &|l, r| l != r)
     }
 
-    pub fn add(l: &Tensor, r: &Tensor) -> Result<Tensor, ShapeError> {
-        elementwise_broadcasted_map(l, r, &|l, r| l + r)
+    pub fn lt(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("lt", l, r, &|l, r| l < r)
     }
 
-    pub fn mul(l: &Tensor, r: &Tensor) -> Result<Tensor, ShapeError> {
-        elementwise_broadcasted_map(l, r, &|l, r| l * r)
+    pub fn le(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("le", l, r, &|l, r| l <= r)
     }
 
-    pub fn sub(l: &Tensor, r: &Tensor) -> Result<Tensor, ShapeError> {
-        elementwise_broadcasted_map(l, r, &|l, r| l - r)
+    pub fn gt(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("gt", l, r, &|l, r| l > r)
     }
 
-    pub fn div(l: &Tensor, r: &Tensor) -> Result<Tensor, ShapeError> {
-        elementwise_broadcasted_map(l, r, &|l, r| l / r)
+    pub fn ge(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("ge", l, r, &|l, r| l >= r)
     }
 
-    pub fn sqr(t: &Tensor) -> Result<Tensor, ShapeError> {
-        elementwise_map(t, &|a| a * a)
+    // LOGICAL
+    // any nonzero element counts as true. the results are 1/0 masks.
+
+    pub fn logical_and(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("logical_and", l, r, &|l, r| l != 0. && r != 0.)
     }
 
-    /// extremely naive implementation of mean
-    pub fn mean(val: &Tensor) -> Tensor {
-        let n: usize = val.shape.iter().product();
-        let sum = val.data.iter().sum::<f64>();
-        Tensor::from(sum / n as f64)
+    pub fn logical_or(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        compare("logical_or", l, r, &|l, r| l != 0. || r != 0.)
     }
 
-    pub fn relu(t: &Tensor) -> Tensor {
-        Self::mul(&Self::gt(t, 0.), t).unwrap()
+    pub fn logical_not(t: &Tensor) -> Tensor {
+        elementwise_map_as(t, DType::Bool, &|v| if v == 0. { 1. } else { 0. }).unwrap()
     }
 
-    /// creates a mask of 1s and 0s where the input tensor is greater than the threshold
-    pub fn gt(val: &Tensor, threshold: f64) -> Tensor {
-        elementwise_map(val, &|v| if v > threshold { 1. } else { 0. }).unwrap()
+    /// `a` where `cond` is nonzero, `b` elsewhere. all three broadcast together.
+    pub fn where_(cond: &Tensor, a: &Tensor, b: &Tensor) -> Result<Tensor, RaxError> {
+        let shape = broadcast_shapes(&cond.shape, &a.shape)
+            .ok_or_else(|| RaxError::shape("where", &cond.shape, &a.shape))?;
+        let shape = broadcast_shapes(&shape, &b.shape).ok_or_else(|| RaxError::shape("where", &shape, &b.shape))?;
+        let (cond, a, b) = (cond.expand(&shape), a.expand(&shape), b.expand(&shape));
+        let data = cond
+            .strided_offsets()
+            .zip(a.strided_offsets())
+            .zip(b.strided_offsets())
+            .map(|((c, i), j)| if cond.data.get(c) != 0. { a.data.get(i) } else { b.data.get(j) })
+            .collect();
+        Ok(Tensor::new_as(data, &shape, result_dtype(&a, &b)))
     }
 
     pub fn mmul(l: &Tensor, r: &Tensor) -> Tensor {
         l.matmul(r)
     }
+
+    pub fn try_mmul(l: &Tensor, r: &Tensor) -> Result<Tensor, RaxError> {
+        l.try_matmul(r)
+    }
+
+    /// The inverse of broadcasting `shape` up to `self.size()`: sums over every
+    /// dimension that was broadcast (leading dimensions that `shape` doesn't
+    /// have, and dimensions where `shape` is 1) so the result has `shape` exactly.
+    ///
+    /// Used to bring the gradient of a broadcasted binary op back to the shape
+    /// of its argument.
+    pub fn unbroadcast(&self, shape: &[usize]) -> Result<Tensor, RaxError> {
+        if self.shape == shape {
+            return Ok(self.clone());
+        }
+
+        let mismatch = || RaxError::shape("unbroadcast", &self.shape, shape);
+        let broadcast_dirs = get_broadcast_directions(shape.to_vec(), self.shape.clone()).ok_or_else(mismatch)?;
+
+        // `self` must be what `shape` broadcasts *to*, never the other way round
+        if shape.len() > self.shape.len() || broadcast_dirs.contains(&BroadcastDir::RtL) {
+            return Err(mismatch());
+        }
+
+        let mut out = VecTensor::zeroes(shape);
+
+        for_each_index(&self.shape, |idx| {
+            *out.at_mut(&broadcast_index(idx, shape)) += self.at(idx).unwrap();
+        });
+
+        Ok(out.into_tensor())
+    }
     
     pub fn ones(shape: &[usize]) -> Tensor {
-        let capacity = shape.iter().product();
-        Tensor {
-            data: vec![1.; capacity],
-            shape: shape.to_vec(),
-            stride: Tensor::get_postfix_prod(shape),
-        }
+        VecTensor::ones(shape).into_tensor()
     }
 }
 
@@ -372,146 +1362,381 @@ fn get_broadcast_directions(shape_l: Vec<usize>, shape_r: Vec<usize>) -> Option<
     Some(out)
 }
 
-#[derive(Debug)]
-pub struct ShapeError;
+/// The dtype a binary op between `l` and `r` computes in: the promotion of the
+/// two, except that a 0-d tensor (like `Tensor::from(2.)`) only counts if it's
+/// of a higher category (bool < int < float). So an f32 tensor times a plain
+/// f64 scalar stays f32, like in pytorch.
+pub(crate) fn result_dtype(l: &Tensor, r: &Tensor) -> DType {
+    let (dl, dr) = (l.dtype(), r.dtype());
+    match (l.shape.is_empty(), r.shape.is_empty()) {
+        (true, false) if dl.category() <= dr.category() => dr,
+        (false, true) if dr.category() <= dl.category() => dl,
+        _ => dl.promote(dr),
+    }
+}
+
+/// `elementwise_func` over `l` and `r` broadcast together, giving the
+/// arithmetic dtype of the two (see `result_dtype`). `op` names the op in errors.
+pub(crate) fn elementwise_broadcasted_map(
+    op: &'static str,
+    l: &Tensor,
+    r: &Tensor,
+    elementwise_func: &impl Fn(f64, f64) -> f64,
+) -> Result<Tensor, RaxError> {
+    elementwise_broadcasted_map_as(op, l, r, result_dtype(l, r).to_arith(), elementwise_func)
+}
 
-fn elementwise_broadcasted_map(
+pub(crate) fn elementwise_broadcasted_map_as(
+    op: &'static str,
     l: &Tensor,
     r: &Tensor,
+    dtype: DType,
     elementwise_func: &impl Fn(f64, f64) -> f64,
-) -> Result<Tensor, ShapeError> {
-    let broadcast_dirs =
-        get_broadcast_directions(l.shape.clone(), r.shape.clone()).ok_or(ShapeError)?;
+) -> Result<Tensor, RaxError> {
+    let out_shape = broadcast_shapes(&l.shape, &r.shape).ok_or_else(|| RaxError::shape(op, &l.shape, &r.shape))?;
+
+    // fast path: same layout on both sides, so the buffers line up element for element
+    if l.shape == r.shape && l.is_contiguous() && r.is_contiguous() {
+        let (l_data, r_data) = (l.contiguous_data(), r.contiguous_data());
+        let data = zip(l_data.iter(), r_data.iter()).map(|(a, b)| elementwise_func(*a, *b)).collect();
+        return Ok(Tensor::new_as(data, &out_shape, dtype));
+    }
 
-    // give empty leading dimensions to the smaller tensor if needed
-    // as it dramatically simplifies syncing the pointers
-    let mut inner_r = r.clone();
-    let mut inner_l = l.clone();
-    while inner_r.shape.len() < broadcast_dirs.len() {
-        inner_r.unsqueeze_(0);
+    // fast path: one side is a single value and the other is already the output
+    if r.n_elements() == 1 && l.shape == out_shape && l.is_contiguous() {
+        let b = r.data.get(r.offset);
+        let data = l.contiguous_data().iter().map(|a| elementwise_func(*a, b)).collect();
+        return Ok(Tensor::new_as(data, &out_shape, dtype));
     }
-    while inner_l.shape.len() < broadcast_dirs.len() {
-        inner_l.unsqueeze_(0);
+    if l.n_elements() == 1 && r.shape == out_shape && r.is_contiguous() {
+        let a = l.data.get(l.offset);
+        let data = r.contiguous_data().iter().map(|b| elementwise_func(a, *b)).collect();
+        return Ok(Tensor::new_as(data, &out_shape, dtype));
     }
 
-    let mut idx_stack: Vec<usize> = vec![];
-    let mut r_stack: Vec<usize> = vec![];
-    let mut l_stack: Vec<usize> = vec![];
-
-    let elemwise_max_shape: Vec<usize> = zip(inner_l.shape.clone(), inner_r.shape.clone())
-        .map(max)
+    let l_offsets = StridedOffsets::new(&out_shape, l.broadcast_stride(&out_shape), l.offset);
+    let r_offsets = StridedOffsets::new(&out_shape, r.broadcast_stride(&out_shape), r.offset);
+    let data = zip(l_offsets, r_offsets)
+        .map(|(i, j)| elementwise_func(l.data.get(i), r.data.get(j)))
         .collect();
 
-    let mut out = VecTensor::zeroes(&elemwise_max_shape);
-
-    #[allow(clippy::too_many_arguments)]
-    fn inner(
-        r: &Tensor,
-        r_stack: &mut Vec<usize>,
-        l: &Tensor,
-        l_stack: &mut Vec<usize>,
-        out: &mut VecTensor,
-        out_stack: &mut Vec<usize>,
-        broadcast_dirs: &Vec<BroadcastDir>,
-        elementwise_func: &impl Fn(f64, f64) -> f64,
-    ) {
-        let full_depth = broadcast_dirs.len();
-        let depth = out_stack.len();
-
-        if depth == full_depth {
-            *out.at_mut(out_stack) = elementwise_func(l.at(l_stack).unwrap(), r.at(r_stack).unwrap());
-            return;
-        }
-
-        let broadcast_direction_for_depth = &broadcast_dirs[depth];
-        let out_dim_size_for_depth = out.shape[depth];
-
-        for dim_idx in 0..out_dim_size_for_depth {
-            out_stack.push(dim_idx);
-
-            // if theres a broadcast to be done for this
-            // dimension, push `0` onto the appropriate
-            // stack so that the pointer stays pointing
-            // at index 0 for that dimension
-            r_stack.push(if broadcast_direction_for_depth == &BroadcastDir::RtL {
-                0
-            } else {
-                dim_idx
-            });
-            l_stack.push(if broadcast_direction_for_depth == &BroadcastDir::LtR {
-                0
-            } else {
-                dim_idx
-            });
+    Ok(Tensor::new_as(data, &out_shape, dtype))
+}
+
+pub(crate) fn sigmoid(a: f64) -> f64 {
+    if a >= 0. {
+        1. / (1. + (-a).exp())
+    } else {
+        let e = a.exp();
+        e / (1. + e)
+    }
+}
+
+/// sqrt(2/π) (x + 0.044715 x^3), the argument to tanh in `Tensor::gelu`
+pub(crate) fn gelu_inner(a: f64) -> f64 {
+    (2. / std::f64::consts::PI).sqrt() * (a + 0.044715 * a.powi(3))
+}
+
+fn compare(op: &'static str, l: &Tensor, r: &Tensor, func: &impl Fn(f64, f64) -> bool) -> Result<Tensor, RaxError> {
+    elementwise_broadcasted_map_as(op, l, r, DType::Bool, &|l, r| if func(l, r) { 1. } else { 0. })
+}
+
+/// index tensors must be I64
+fn check_index_tensor(op: &'static str, index: &Tensor) -> Result<(), RaxError> {
+    if index.dtype() != DType::I64 {
+        return Err(RaxError::DTypeMismatch {
+            op,
+            expected: DType::I64,
+            got: index.dtype(),
+        });
+    }
+    Ok(())
+}
+
+/// an entry of an I64 index tensor as a position along a dimension
+fn index_position(op: &'static str, value: f64) -> Result<usize, RaxError> {
+    let i = value as i64;
+    usize::try_from(i).map_err(|_| RaxError::NegativeIndex { op, index: i })
+}
+
+/// `func` over every element. floats keep their dtype, ints and bools give f64.
+pub(crate) fn elementwise_map(t: &Tensor, func: &impl Fn(f64) -> f64) -> Result<Tensor, RaxError> {
+    elementwise_map_as(t, t.dtype().to_float(), func)
+}
+
+pub(crate) fn elementwise_map_as(t: &Tensor, dtype: DType, func: &impl Fn(f64) -> f64) -> Result<Tensor, RaxError> {
+    let data = if t.is_contiguous() {
+        t.contiguous_data().iter().map(|v| func(*v)).collect()
+    } else {
+        t.strided_offsets().map(|i| func(t.data.get(i))).collect()
+    };
+    Ok(Tensor::new_as(data, &t.shape, dtype))
+}
 
-            inner(r, r_stack, l, l_stack, out, out_stack, broadcast_dirs, elementwise_func);
+/// Iterates over the buffer offsets of the elements of a strided tensor, visiting
+/// `shape` in row-major order. Instead of recomputing each offset from a
+/// multi-index, it keeps an odometer-style index and bumps a running offset by
+/// the stride of whichever dimension ticked over.
+///
+/// A stride of 0 pins that dimension to a single element, which is how
+/// broadcasting is expressed.
+struct StridedOffsets {
+    shape: Vec<usize>,
+    stride: Vec<usize>,
+    idx: Vec<usize>,
+    offset: usize,
+    remaining: usize,
+}
+
+impl StridedOffsets {
+    fn new(shape: &[usize], stride: Vec<usize>, start: usize) -> StridedOffsets {
+        assert_eq!(shape.len(), stride.len());
+        StridedOffsets {
+            shape: shape.to_vec(),
+            stride,
+            idx: vec![0; shape.len()],
+            offset: start,
+            remaining: shape.iter().product(),
+        }
+    }
+}
+
+impl Iterator for StridedOffsets {
+    type Item = usize;
 
-            out_stack.pop();
-            r_stack.pop();
-            l_stack.pop();
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let current = self.offset;
+
+        for d in (0..self.shape.len()).rev() {
+            self.idx[d] += 1;
+            if self.idx[d] < self.shape[d] {
+                self.offset += self.stride[d];
+                break;
+            }
+            // wrap this dimension back to 0 and carry into the next one
+            self.offset -= self.stride[d] * (self.shape[d] - 1);
+            self.idx[d] = 0;
         }
+
+        Some(current)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
     }
+}
 
-    inner(
-        &inner_r,
-        &mut r_stack,
-        &inner_l,
-        &mut l_stack,
-        &mut out,
-        &mut idx_stack,
-        &broadcast_dirs,
-        elementwise_func,
-    );
+impl ExactSizeIterator for StridedOffsets {}
 
-    Ok(out.as_tensor())
+fn normalize_axis(axis: isize, n_dims: usize) -> usize {
+    try_normalize_axis(axis, n_dims).or_panic()
 }
 
-fn elementwise_map(t: &Tensor, func: &impl Fn(f64) -> f64) -> Result<Tensor, ShapeError> {
-    let mut idx_stack: Vec<usize> = vec![];
+pub(crate) fn try_normalize_axis(axis: isize, n_dims: usize) -> Result<usize, RaxError> {
+    let dim = if axis < 0 {
+        n_dims.checked_sub(axis.unsigned_abs())
+    } else {
+        Some(axis as usize).filter(|d| *d < n_dims)
+    };
+    dim.ok_or(RaxError::AxisOutOfRange { axis, n_dims })
+}
+
+/// sorted, deduplicated, non-negative axes. empty means "all axes".
+fn normalize_axes(axes: &[isize], n_dims: usize) -> Vec<usize> {
+    if axes.is_empty() {
+        return (0..n_dims).collect();
+    }
+    let mut out: Vec<usize> = axes.iter().map(|a| normalize_axis(*a, n_dims)).collect();
+    out.sort();
+    out.dedup();
+    out
+}
+
+/// index into the reduced tensor that the element at `idx` folds into
+fn reduced_index(idx: &[usize], axes: &[usize], keepdim: bool) -> Vec<usize> {
+    let mut out = vec![];
+    for (i, v) in idx.iter().enumerate() {
+        if !axes.contains(&i) {
+            out.push(*v);
+        } else if keepdim {
+            out.push(0);
+        }
+    }
+    out
+}
+
+/// the shape two shapes broadcast to, if they're compatible
+pub(crate) fn broadcast_shapes(shape_l: &[usize], shape_r: &[usize]) -> Option<Vec<usize>> {
+    get_broadcast_directions(shape_l.to_vec(), shape_r.to_vec())?;
+    let n = shape_l.len().max(shape_r.len());
+    let dim = |shape: &[usize], i: usize| {
+        if i < n - shape.len() {
+            1
+        } else {
+            shape[i - (n - shape.len())]
+        }
+    };
+    Some((0..n).map(|i| dim(shape_l, i).max(dim(shape_r, i))).collect())
+}
 
-    let mut out = VecTensor::zeroes(&t.shape);
+/// maps an index into a broadcast shape back to an index into `shape`, which
+/// broadcast to it. `shape` is right-aligned, and its size-1 dimensions pin to 0.
+fn broadcast_index(idx: &[usize], shape: &[usize]) -> Vec<usize> {
+    let n_leading = idx.len() - shape.len();
+    zip(&idx[n_leading..], shape)
+        .map(|(i, dim)| if *dim == 1 { 0 } else { *i })
+        .collect()
+}
 
-    fn inner(
-        t: &Tensor,
-        out: &mut VecTensor,
-        idx_stack: &mut Vec<usize>,
-        func: &impl Fn(f64) -> f64,
-        full_depth: usize,
-    ) {
-        let depth = idx_stack.len();
+/// The shapes involved in a (promoted, at least 2-D) batched matmul
+struct MatmulDims {
+    l_batch: Vec<usize>,
+    r_batch: Vec<usize>,
+    batch_shape: Vec<usize>,
+    h: usize,
+    inner: usize,
+    w: usize,
+}
 
-        if depth == full_depth {
-            *out.at_mut(idx_stack) = func(t.at(idx_stack).unwrap());
-            return;
+impl MatmulDims {
+    fn new(l: &Tensor, r: &Tensor) -> MatmulDims {
+        let (l_nd, r_nd) = (l.shape.len(), r.shape.len());
+        assert!(l_nd >= 2 && r_nd >= 2);
+        assert!(
+            l.shape[l_nd - 1] == r.shape[r_nd - 2],
+            "matmul inner dimensions don't match: {:?} and {:?}",
+            l.shape,
+            r.shape
+        );
+
+        let (l_batch, r_batch) = (&l.shape[..l_nd - 2], &r.shape[..r_nd - 2]);
+        let batch_shape = broadcast_shapes(l_batch, r_batch).unwrap_or_else(|| {
+            panic!(
+                "matmul batch dimensions don't broadcast: {:?} and {:?}",
+                l.shape, r.shape
+            )
+        });
+
+        MatmulDims {
+            l_batch: l_batch.to_vec(),
+            r_batch: r_batch.to_vec(),
+            batch_shape,
+            h: l.shape[l_nd - 2],
+            inner: l.shape[l_nd - 1],
+            w: r.shape[r_nd - 1],
         }
+    }
+
+    fn out_shape(&self) -> Vec<usize> {
+        let mut out = self.batch_shape.clone();
+        out.extend([self.h, self.w]);
+        out
+    }
+}
+
+/// rows of the output are computed in blocks of this many, and the inner and
+/// column loops are tiled by the same amount so a tile of `r` stays in cache
+const MATMUL_BLOCK: usize = 64;
+
+/// below this many multiply-adds it isn't worth waking up the thread pool
+#[cfg(feature = "parallel")]
+const MATMUL_PARALLEL_THRESHOLD: usize = 1 << 15;
+
+/// `out += l @ r` for row-major `l: [h, k]`, `r: [k, w]`, `out: [h, w]`.
+/// With the `parallel` feature, blocks of output rows are spread over rayon's pool.
+fn matmul_kernel(l: &[f64], r: &[f64], out: &mut [f64], k: usize, w: usize) {
+    #[cfg(feature = "parallel")]
+    if out.len() * k >= MATMUL_PARALLEL_THRESHOLD {
+        use rayon::prelude::*;
+        out.par_chunks_mut(MATMUL_BLOCK * w)
+            .enumerate()
+            .for_each(|(block, out_rows)| matmul_block(l, r, out_rows, block * MATMUL_BLOCK, k, w));
+        return;
+    }
+
+    for (block, out_rows) in out.chunks_mut(MATMUL_BLOCK * w).enumerate() {
+        matmul_block(l, r, out_rows, block * MATMUL_BLOCK, k, w);
+    }
+}
 
-        for dim_idx in 0..out.shape[depth] {
-            idx_stack.push(dim_idx);
-            inner(t, out, idx_stack, func, full_depth);
-            idx_stack.pop();
+/// computes the rows of the output starting at `row_start` into `out_rows`.
+/// loops are ordered i-k-j so the innermost loop runs along contiguous rows of
+/// both `r` and `out`.
+fn matmul_block(l: &[f64], r: &[f64], out_rows: &mut [f64], row_start: usize, k: usize, w: usize) {
+    let n_rows = out_rows.len() / w;
+    for kk in (0..k).step_by(MATMUL_BLOCK) {
+        let k_end = (kk + MATMUL_BLOCK).min(k);
+        for jj in (0..w).step_by(MATMUL_BLOCK) {
+            let j_end = (jj + MATMUL_BLOCK).min(w);
+            for i in 0..n_rows {
+                let l_row = &l[(row_start + i) * k..(row_start + i + 1) * k];
+                let out_row = &mut out_rows[i * w + jj..i * w + j_end];
+                for (p, a) in l_row.iter().enumerate().take(k_end).skip(kk) {
+                    let r_row = &r[p * w + jj..p * w + j_end];
+                    for (o, b) in zip(out_row.iter_mut(), r_row) {
+                        *o += a * b;
+                    }
+                }
+            }
         }
     }
+}
 
-    inner(t, &mut out, &mut idx_stack, func, t.size().len());
+fn flat_offset(idx: &[usize], stride: &[usize]) -> usize {
+    zip(idx, stride).map(|(i, s)| i * s).sum()
+}
 
-    Ok(out.as_tensor())
+/// calls `func` with every multi-index into `shape`, in row-major order
+/// `for_each_index`, stopping at the first error
+fn try_for_each_index(
+    shape: &[usize],
+    mut func: impl FnMut(&Vec<usize>) -> Result<(), RaxError>,
+) -> Result<(), RaxError> {
+    let mut result = Ok(());
+    for_each_index(shape, |idx| {
+        if result.is_ok() {
+            result = func(idx);
+        }
+    });
+    result
 }
 
-fn max((a, b): (usize, usize)) -> usize {
-    if a > b {
-        a
-    } else {
-        b
+fn for_each_index(shape: &[usize], mut func: impl FnMut(&Vec<usize>)) {
+    if shape.contains(&0) {
+        return;
+    }
+    let mut idx = vec![0; shape.len()];
+    loop {
+        func(&idx);
+
+        // odometer-style increment of the multi-index
+        let mut d = idx.len();
+        loop {
+            if d == 0 {
+                return;
+            }
+            d -= 1;
+            idx[d] += 1;
+            if idx[d] < shape[d] {
+                break;
+            }
+            idx[d] = 0;
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::assert_close;
 
     #[test]
     fn test_transpose() {
-        let a = Tensor::rand(&vec![4, 4]);
+        let a = Tensor::rand(&[4, 4]);
         let a_t = a.transpose(0, 1);
         assert!(a.at(&vec![2, 3]).unwrap() == a_t.at(&vec![3, 2]).unwrap());
     }
@@ -581,7 +1806,7 @@ mod tests {
 
     #[test]
     fn test_broadcasting_7() {
-        let t = Tensor::rand(&vec![1, 2, 3]);
+        let t = Tensor::rand(&[1, 2, 3]);
         let scalar: &[f64] = &[1.];
         let scalar = Tensor::from(scalar);
         Tensor::add(&t, &scalar).unwrap();
@@ -589,17 +1814,9 @@ mod tests {
 
     #[test]
     fn test_add() {
-        let l = Tensor {
-            data: vec![1., 2., 3.],
-            shape: vec![1, 3],
-            stride: vec![3, 1],
-        };
+        let l = Tensor::new(vec![1., 2., 3.], &[1, 3]);
 
-        let r = Tensor {
-            data: vec![7., 2.],
-            shape: vec![1, 2, 1],
-            stride: Tensor::get_postfix_prod(&vec![1, 2, 1]),
-        };
+        let r = Tensor::new(vec![7., 2.], &[1, 2, 1]);
 
         let res = Tensor::add(&l, &r).unwrap();
         println!("{:?}", res);
@@ -608,30 +1825,567 @@ mod tests {
 
     #[test]
     fn test_display() {
-        let md = Tensor {
-            data: (0..24).map(|i| i as f64).collect::<Vec<f64>>(),
-            shape: vec![4, 2, 3],
-            stride: Tensor::get_postfix_prod(&vec![4, 2, 3]),
-        };
+        let md = Tensor::new((0..24).map(|i| i as f64).collect::<Vec<f64>>(), &[4, 2, 3]);
         println!("{:?}", md);
     }
 
     #[test]
     fn test_sub() {
-        let l = Tensor {
-            data: vec![3.],
-            shape: vec![],
-            stride: vec![1],
-        };
+        let l = Tensor::new(vec![3.], &[]);
 
-        let r = Tensor {
-            data: vec![2.],
-            shape: vec![],
-            stride: vec![1],
-        };
+        let r = Tensor::new(vec![2.], &[]);
 
         let res = Tensor::sub(&l, &r).unwrap();
 
         assert!(res.item().unwrap() == 1.);
     }
+
+    #[test]
+    fn test_unbroadcast() {
+        let t = Tensor::new((0..6).map(|i| i as f64).collect(), &[2, 3]);
+
+        let bias_grad = t.unbroadcast(&[3]).unwrap();
+        assert_eq!(bias_grad.size(), &[3]);
+        assert_eq!(bias_grad.to_vec(), vec![3., 5., 7.]);
+
+        let col_grad = t.unbroadcast(&[2, 1]).unwrap();
+        assert_eq!(col_grad.size(), &[2, 1]);
+        assert_eq!(col_grad.to_vec(), vec![3., 12.]);
+
+        let scalar_grad = t.unbroadcast(&[]).unwrap();
+        assert_eq!(scalar_grad.item().unwrap(), 15.);
+
+        assert!(t.unbroadcast(&[2]).is_err());
+        assert!(t.unbroadcast(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_reduce() {
+        let t = Tensor::new(vec![1., 5., 2., 4., 0., 6.], &[2, 3]);
+
+        let s = t.sum(&[1], false);
+        assert_eq!(s.size(), &[2]);
+        assert_eq!(s.to_vec(), vec![8., 10.]);
+
+        let s = t.sum(&[0], true);
+        assert_eq!(s.size(), &[1, 3]);
+        assert_eq!(s.to_vec(), vec![5., 5., 8.]);
+
+        assert_eq!(t.sum(&[], false).item().unwrap(), 18.);
+        assert_eq!(t.mean(&[0, 1], false).item().unwrap(), 3.);
+        assert_eq!(t.mean(&[-1], false).to_vec(), vec![8. / 3., 10. / 3.]);
+        assert_eq!(t.max(&[1], false).to_vec(), vec![5., 6.]);
+        assert_eq!(t.min(&[0], false).to_vec(), vec![1., 0., 2.]);
+        assert_eq!(t.prod(&[1], true).to_vec(), vec![10., 0.]);
+    }
+
+    #[test]
+    fn test_extremum_masks() {
+        let t = Tensor::new(vec![3., 1., 3., 0., 2., 2.], &[2, 3]);
+
+        // ties go to the first occurrence
+        assert_eq!(t.argmax_mask(&[1]).to_vec(), vec![1., 0., 0., 0., 1., 0.]);
+        assert_eq!(t.argmin_mask(&[0]).to_vec(), vec![0., 1., 0., 1., 0., 1.]);
+    }
+
+    #[test]
+    fn test_prod_others() {
+        let t = Tensor::new(vec![2., 3., 4., 0., 5., 6., 0., 0., 7.], &[3, 3]);
+
+        let g = t.prod_others(&[1]);
+        assert_eq!(g.to_vec(), vec![12., 8., 6., 30., 0., 0., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_matmul_batched() {
+        // [2, 1, 2, 3] @ [3, 3, 2] -> [2, 3, 2, 2]
+        let l = Tensor::new((0..12).map(|i| i as f64).collect(), &[2, 1, 2, 3]);
+        let r = Tensor::new((0..18).map(|i| i as f64).collect(), &[3, 3, 2]);
+        let out = l.matmul(&r);
+        assert_eq!(out.size(), &[2, 3, 2, 2]);
+
+        // check every batch against the plain 2-D product
+        for b0 in 0..2 {
+            for b1 in 0..3 {
+                let l_mat = Tensor::new(l.to_vec()[b0 * 6..(b0 + 1) * 6].to_vec(), &[2, 3]);
+                let r_mat = Tensor::new(r.to_vec()[b1 * 6..(b1 + 1) * 6].to_vec(), &[3, 2]);
+                let expected = l_mat.matmul(&r_mat);
+                for i in 0..2 {
+                    for j in 0..2 {
+                        assert_eq!(
+                            out.at(&vec![b0, b1, i, j]).unwrap(),
+                            expected.at(&vec![i, j]).unwrap()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_matmul_vector_promotion() {
+        let m = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let v3 = Tensor::from(&[1., 0., -1.] as &[f64]);
+        let v2 = Tensor::from(&[1., 1.] as &[f64]);
+
+        let mv = m.matmul(&v3);
+        assert_eq!(mv.size(), &[2]);
+        assert_eq!(mv.to_vec(), vec![-2., -2.]);
+
+        let vm = v2.matmul(&m);
+        assert_eq!(vm.size(), &[3]);
+        assert_eq!(vm.to_vec(), vec![5., 7., 9.]);
+
+        let dot = v3.matmul(&v3);
+        assert_eq!(dot.size(), &[] as &[usize]);
+        assert_eq!(dot.item().unwrap(), 2.);
+    }
+
+    #[test]
+    fn test_matmul_matches_naive() {
+        // big enough to span several blocks in every direction, and not a multiple of the block size
+        let l = Tensor::rand(&[2, 70, 130]);
+        let r = Tensor::rand(&[130, 65]);
+        assert_close(&l.matmul(&r), &l.matmul_naive(&r));
+
+        // strided views go through the gather path
+        let r_t = Tensor::rand(&[65, 130]).transpose(0, 1);
+        assert!(!r_t.is_contiguous());
+        assert_close(&l.matmul(&r_t), &l.matmul_naive(&r_t));
+
+        // broadcast batch dims on both sides
+        let l = Tensor::rand(&[3, 1, 5, 4]);
+        let r = Tensor::rand(&[2, 4, 6]);
+        assert_close(&l.matmul(&r), &l.matmul_naive(&r));
+    }
+
+    #[test]
+    fn test_strided_offsets() {
+        // a [2, 3] tensor walked through a stride of [1, 2] (i.e. a transposed [3, 2])
+        let offsets: Vec<usize> = StridedOffsets::new(&[2, 3], vec![1, 2], 0).collect();
+        assert_eq!(offsets, vec![0, 2, 4, 1, 3, 5]);
+
+        // broadcasting a [3] row down 2 rows
+        let offsets: Vec<usize> = StridedOffsets::new(&[2, 3], vec![0, 1], 0).collect();
+        assert_eq!(offsets, vec![0, 1, 2, 0, 1, 2]);
+
+        let offsets: Vec<usize> = StridedOffsets::new(&[], vec![], 3).collect();
+        assert_eq!(offsets, vec![3]);
+    }
+
+    #[test]
+    fn test_elementwise_strided_and_broadcast() {
+        let a = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[3, 2]).transpose(0, 1);
+        let row = Tensor::new(vec![10., 20., 30.], &[1, 3]);
+        let col = Tensor::new(vec![100., 200.], &[2, 1]);
+
+        // transposed [2, 3] view + broadcast row
+        assert_eq!(Tensor::add(&a, &row).unwrap().to_vec(), vec![11., 23., 35., 12., 24., 36.]);
+
+        // column against row broadcasts both ways
+        let outer = Tensor::add(&col, &row).unwrap();
+        assert_eq!(outer.size(), &[2, 3]);
+        assert_eq!(outer.to_vec(), vec![110., 120., 130., 210., 220., 230.]);
+
+        // scalar fast paths on either side
+        assert_eq!(Tensor::sub(&row, &Tensor::from(1.)).unwrap().to_vec(), vec![9., 19., 29.]);
+        assert_eq!(Tensor::sub(&Tensor::from(1.), &row).unwrap().to_vec(), vec![-9., -19., -29.]);
+
+        // unary ops on a strided view come out contiguous
+        let sq = Tensor::sqr(&a).unwrap();
+        assert!(sq.is_contiguous());
+        assert_eq!(sq.to_vec(), vec![1., 9., 25., 4., 16., 36.]);
+    }
+
+    #[test]
+    fn test_views_share_storage() {
+        let t = Tensor::new((0..6).map(|i| i as f64).collect(), &[2, 3]);
+
+        let t_t = t.transpose(0, 1);
+        let row = t.narrow(0, 1, 1);
+        let col = t.narrow(-1, 2, 1);
+        let flat = t.reshape(&[6]);
+        let wide = Tensor::new(vec![1., 2., 3.], &[3]).unsqueeze(0).expand(&[4, 3]);
+
+        for view in [&t_t, &row, &col, &flat] {
+            assert!(Rc::ptr_eq(&view.data, &t.data));
+        }
+
+        assert_eq!(t_t.to_vec(), vec![0., 3., 1., 4., 2., 5.]);
+        assert_eq!(row.to_vec(), vec![3., 4., 5.]);
+        assert_eq!(col.size(), &[2, 1]);
+        assert_eq!(col.to_vec(), vec![2., 5.]);
+        assert_eq!(flat.at(&vec![4]).unwrap(), 4.);
+        assert_eq!(wide.size(), &[4, 3]);
+        assert_eq!(wide.data.len(), 3);
+        assert_eq!(wide.at(&vec![3, 1]).unwrap(), 2.);
+        assert!(t.at(&vec![2, 0]).is_err());
+
+        // a contiguous narrow is still contiguous, just offset into the buffer
+        assert!(row.is_contiguous());
+        assert!(Rc::ptr_eq(&row.contiguous().data, &t.data));
+
+        // reshaping a non-contiguous view has to copy
+        let t_t_flat = t_t.reshape(&[6]);
+        assert!(!Rc::ptr_eq(&t_t_flat.data, &t.data));
+        assert_eq!(t_t_flat.to_vec(), vec![0., 3., 1., 4., 2., 5.]);
+        assert_eq!(t_t.contiguous().stride, vec![2, 1]);
+
+        // elementwise ops read views correctly
+        assert_eq!(Tensor::add(&col, &row).unwrap().to_vec(), vec![5., 6., 7., 8., 9., 10.]);
+        assert_eq!(Tensor::mul(&wide, &Tensor::from(2.)).unwrap().to_vec()[..3], [2., 4., 6.]);
+    }
+
+    #[test]
+    fn test_shape_ops() {
+        let t = Tensor::new((0..24).map(|i| i as f64).collect(), &[2, 3, 4]);
+
+        assert_eq!(t.unsqueeze(0).size(), &[1, 2, 3, 4]);
+        assert_eq!(t.unsqueeze(-1).size(), &[2, 3, 4, 1]);
+        assert_eq!(t.unsqueeze(-2).size(), &[2, 3, 1, 4]);
+        assert_eq!(t.unsqueeze(1).squeeze(1).size(), &[2, 3, 4]);
+        // squeezing a dimension that isn't size 1 does nothing
+        assert_eq!(t.squeeze(0).size(), &[2, 3, 4]);
+
+        assert_eq!(t.flatten(0, -1).size(), &[24]);
+        assert_eq!(t.flatten(1, 2).size(), &[2, 12]);
+        assert_eq!(t.flatten(0, 1).size(), &[6, 4]);
+        assert_eq!(Tensor::from(1.).flatten(0, -1).size(), &[1]);
+
+        let p = t.permute(&[2, 0, 1]);
+        assert_eq!(p.size(), &[4, 2, 3]);
+        assert_eq!(p.at(&vec![3, 1, 2]).unwrap(), t.at(&vec![1, 2, 3]).unwrap());
+        assert!(Rc::ptr_eq(&p.data, &t.data));
+
+        assert_eq!(t.view(&[4, 6]).size(), &[4, 6]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_view_non_contiguous() {
+        Tensor::rand(&[2, 3]).transpose(0, 1).view(&[6]);
+    }
+
+    #[test]
+    fn test_slice() {
+        let t = Tensor::new((0..20).map(|i| i as f64).collect(), &[4, 5]);
+
+        // t[:, 1:3]
+        let s = t.slice(&[(..).into(), (1..3).into()]);
+        assert_eq!(s.size(), &[4, 2]);
+        assert_eq!(s.to_vec(), vec![1., 2., 6., 7., 11., 12., 16., 17.]);
+        assert!(Rc::ptr_eq(&s.data, &t.data));
+
+        // t[1::2, ::3]
+        let every_other = SliceRange::from(..).step_by(2);
+        let every_third = SliceRange::from(..).step_by(3);
+        let s = t.slice(&[(1..).into()]).slice(&[every_other, every_third]);
+        assert_eq!(s.size(), &[2, 2]);
+        assert_eq!(s.to_vec(), vec![5., 8., 15., 18.]);
+
+        // ends are clamped
+        assert_eq!(t.slice(&[(3..10).into()]).size(), &[1, 5]);
+
+        // t[:2, 1:4] spelled with `..2` and `1..=3`
+        let s = t.slice(&[(..2).into(), (1..=3).into()]);
+        assert_eq!(s.to_vec(), vec![1., 2., 3., 6., 7., 8.]);
+        assert_eq!(SliceRange::from(..2), (0..2).into());
+        assert_eq!(SliceRange::from(2..=usize::MAX), (2..).into());
+
+        let scattered = Tensor::zeros(&[4, 5]).slice_scatter(&[every_other, (1..3).into()], &Tensor::from(1.));
+        assert_eq!(scattered.sum(&[1], false).to_vec(), vec![2., 0., 2., 0.]);
+        assert_eq!(scattered.at(&vec![2, 2]).unwrap(), 1.);
+    }
+
+    #[test]
+    fn test_index_select_and_gather() {
+        let t = Tensor::new((0..6).map(|i| i as f64).collect(), &[3, 2]);
+
+        let rows = t.index_select(0, &[2, 0, 2]);
+        assert_eq!(rows.to_vec(), vec![4., 5., 0., 1., 4., 5.]);
+        let back = Tensor::zeros(&[3, 2]).index_add(0, &[2, 0, 2], &Tensor::ones(&[3, 2]));
+        assert_eq!(back.to_vec(), vec![1., 1., 0., 0., 2., 2.]);
+
+        // pick one column per row
+        let index = Tensor::from_vec(vec![1i64, 0, 1], &[3, 1]);
+        let picked = t.gather(1, &index);
+        assert_eq!(picked.to_vec(), vec![1., 2., 5.]);
+        let back = Tensor::zeros(&[3, 2]).scatter_add(1, &index, &picked);
+        assert_eq!(back.to_vec(), vec![0., 1., 2., 0., 0., 5.]);
+    }
+
+    #[test]
+    fn test_masked_select() {
+        let t = Tensor::new((0..6).map(|i| i as f64).collect(), &[2, 3]);
+        let mask = Tensor::new(vec![1., 0., 1.], &[3]);
+
+        let selected = t.masked_select(&mask);
+        assert_eq!(selected.to_vec(), vec![0., 2., 3., 5.]);
+
+        let back = Tensor::zeros(&[2, 3]).masked_scatter(&mask, &selected);
+        assert_eq!(back.to_vec(), vec![0., 0., 2., 3., 0., 5.]);
+    }
+
+    #[test]
+    fn test_cat_stack_split() {
+        let a = Tensor::new(vec![1., 2., 3., 4.], &[2, 2]);
+        let b = Tensor::new(vec![5., 6.], &[2, 1]);
+
+        let c = Tensor::cat(&[&a, &b], -1);
+        assert_eq!(c.size(), &[2, 3]);
+        assert_eq!(c.to_vec(), vec![1., 2., 5., 3., 4., 6.]);
+
+        let s = Tensor::stack(&[&a, &a, &a], 1);
+        assert_eq!(s.size(), &[2, 3, 2]);
+        assert_eq!(s.at(&vec![1, 2, 0]).unwrap(), 3.);
+        assert_eq!(Tensor::stack(&[&a, &a], -1).size(), &[2, 2, 2]);
+
+        let pieces = c.split(2, 1);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].to_vec(), vec![1., 2., 3., 4.]);
+        assert_eq!(pieces[1].to_vec(), vec![5., 6.]);
+
+        let chunks = Tensor::new((0..10).map(|i| i as f64).collect(), &[10]).chunk(3, 0);
+        let sizes: Vec<usize> = chunks.iter().map(|c| c.size()[0]).collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+
+        assert!(c.try_split(0, 1).is_err());
+        assert!(c.try_chunk(0, 1).is_err());
+        assert!(c.try_split(2, 2).is_err());
+    }
+
+    #[test]
+    fn test_maximum_minimum_pow() {
+        let l = Tensor::new(vec![1., 5., 3., -2.], &[2, 2]);
+        let r = Tensor::from(&[2., 3.] as &[f64]);
+        assert_eq!(Tensor::maximum(&l, &r).unwrap().to_vec(), vec![2., 5., 3., 3.]);
+        assert_eq!(Tensor::minimum(&l, &r).unwrap().to_vec(), vec![1., 3., 2., -2.]);
+        assert_eq!(Tensor::pow_tensor(&l, &r).unwrap().to_vec(), vec![1., 125., 9., -8.]);
+        assert!(Tensor::maximum(&l, &Tensor::zeros(&[3])).is_err());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let l = Tensor::new(vec![1., 2., 3., 4.], &[2, 2]);
+        let r = Tensor::from(&[2., 3.] as &[f64]);
+        assert_eq!(Tensor::eq(&l, &r).unwrap().to_vec(), vec![0., 0., 0., 0.]);
+        assert_eq!(Tensor::eq(&l, &Tensor::from(3.)).unwrap().to_vec(), vec![0., 0., 1., 0.]);
+        assert_eq!(Tensor::ne(&l, &Tensor::from(3.)).unwrap().to_vec(), vec![1., 1., 0., 1.]);
+        assert_eq!(Tensor::lt(&l, &r).unwrap().to_vec(), vec![1., 1., 0., 0.]);
+        assert_eq!(Tensor::le(&l, &r).unwrap().to_vec(), vec![1., 1., 0., 0.]);
+        assert_eq!(Tensor::gt(&l, &r).unwrap().to_vec(), vec![0., 0., 1., 1.]);
+        assert_eq!(Tensor::ge(&l, &Tensor::from(2.)).unwrap().to_vec(), vec![0., 1., 1., 1.]);
+        assert!(Tensor::lt(&l, &Tensor::zeros(&[3])).is_err());
+
+        let a = Tensor::from(&[0., 1., 0., 2.] as &[f64]);
+        let b = Tensor::from(&[0., 0., 3., -1.] as &[f64]);
+        assert_eq!(Tensor::logical_and(&a, &b).unwrap().to_vec(), vec![0., 0., 0., 1.]);
+        assert_eq!(Tensor::logical_or(&a, &b).unwrap().to_vec(), vec![0., 1., 1., 1.]);
+        assert_eq!(Tensor::logical_not(&a).to_vec(), vec![1., 0., 1., 0.]);
+    }
+
+    #[test]
+    fn test_where() {
+        let cond = Tensor::new(vec![1., 0.], &[2, 1]);
+        let a = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let b = Tensor::from(f64::NEG_INFINITY);
+        let w = Tensor::where_(&cond, &a, &b).unwrap();
+        assert_eq!(w.size(), &[2, 3]);
+        assert_eq!(w.to_vec(), vec![1., 2., 3., f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY]);
+
+        // the output is the broadcast of all three
+        let w = Tensor::where_(&Tensor::from(&[1., 0., 1.] as &[f64]), &Tensor::zeros(&[2, 1]), &Tensor::from(9.));
+        assert_eq!(w.unwrap().to_vec(), vec![0., 9., 0., 0., 9., 0.]);
+        assert!(Tensor::where_(&Tensor::ones(&[3]), &a, &Tensor::zeros(&[2])).is_err());
+    }
+
+    #[test]
+    fn test_dtypes() {
+        let i = Tensor::from_vec(vec![1i64, 2, 3, 4], &[2, 2]);
+        assert_eq!(i.dtype(), DType::I64);
+        assert_eq!(i.to_vec(), vec![1., 2., 3., 4.]);
+        assert_eq!(Tensor::from(1.).dtype(), DType::F64);
+
+        // f32 is really stored as f32, and views keep the dtype
+        let f = i.to_dtype(DType::F32);
+        assert!(matches!(*f.data, Storage::F32(ref d) if d.len() == 4));
+        assert_eq!(f.transpose(0, 1).dtype(), DType::F32);
+        assert_eq!(f.transpose(0, 1).contiguous().dtype(), DType::F32);
+        assert_eq!(f.reshape(&[4]).slice(&[(1..3).into()]).dtype(), DType::F32);
+
+        // casting truncates to ints, and anything nonzero is true
+        let x = Tensor::from(&[2.7, -2.7, 0.] as &[f64]);
+        assert_eq!(x.to_dtype(DType::I64).to_vec(), vec![2., -2., 0.]);
+        assert_eq!(x.to_dtype(DType::Bool).to_vec(), vec![1., 1., 0.]);
+        assert_eq!(x.to_dtype(DType::F32).to_dtype(DType::F64).to_vec()[0], 2.7f32 as f64);
+    }
+
+    #[test]
+    fn test_dtype_promotion() {
+        let b = Tensor::from_vec(vec![true, false], &[2]);
+        let i = Tensor::from_vec(vec![1i64, 2], &[2]);
+        let f = Tensor::from_vec(vec![1f32, 2.], &[2]);
+        let d = Tensor::from(&[1., 2.] as &[f64]);
+        let dtype = |t: Result<Tensor, RaxError>| t.unwrap().dtype();
+
+        assert_eq!(dtype(Tensor::add(&i, &i)), DType::I64);
+        assert_eq!(dtype(Tensor::add(&b, &b)), DType::I64);
+        assert_eq!(dtype(Tensor::mul(&b, &f)), DType::F32);
+        assert_eq!(dtype(Tensor::mul(&i, &f)), DType::F32);
+        assert_eq!(dtype(Tensor::sub(&f, &d)), DType::F64);
+        assert_eq!(i.matmul(&f).dtype(), DType::F32);
+        assert_eq!(Tensor::cat(&[&i, &f], 0).dtype(), DType::F32);
+
+        // 0-d tensors don't widen within a category, only across one
+        assert_eq!(dtype(Tensor::mul(&f, &Tensor::from(2.))), DType::F32);
+        assert_eq!(dtype(Tensor::mul(&i, &Tensor::from_vec(vec![2i64], &[]))), DType::I64);
+        assert_eq!(dtype(Tensor::mul(&i, &Tensor::from(0.5))), DType::F64);
+
+        // division and float functions give floats, comparisons give bools
+        let q = Tensor::div(&i, &Tensor::from_vec(vec![2i64], &[])).unwrap();
+        assert_eq!((q.dtype(), q.to_vec()), (DType::F64, vec![0.5, 1.]));
+        assert_eq!(Tensor::exp(&f).dtype(), DType::F32);
+        assert_eq!(Tensor::exp(&i).dtype(), DType::F64);
+        assert_eq!(Tensor::abs(&i).dtype(), DType::I64);
+        assert_eq!(dtype(Tensor::lt(&i, &f)), DType::Bool);
+        assert_eq!(Tensor::logical_not(&i).dtype(), DType::Bool);
+
+        // reductions: counting bools gives ints, means are floats
+        assert_eq!(b.sum(&[], false).dtype(), DType::I64);
+        assert_eq!(b.sum(&[], false).item().unwrap(), 1.);
+        assert_eq!(i.mean(&[], false).dtype(), DType::F64);
+        assert_eq!(f.max(&[], false).dtype(), DType::F32);
+    }
+
+    #[test]
+    fn test_int_and_bool_indices() {
+        let t = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+        let index = Tensor::from_vec(vec![2i64, 0], &[2, 1]);
+        assert_eq!(t.gather(1, &index).to_vec(), vec![3., 4.]);
+
+        let mask = Tensor::from_vec(vec![true, false, true], &[3]);
+        assert_eq!(t.masked_select(&mask).to_vec(), vec![1., 3., 4., 6.]);
+        assert_eq!(Tensor::where_(&mask, &t, &Tensor::from(0.)).unwrap().to_vec(), vec![1., 0., 3., 4., 0., 6.]);
+    }
+
+    #[test]
+    fn test_transcendental() {
+        let t = Tensor::from(&[1., 4.] as &[f64]);
+
+        assert_eq!(Tensor::exp(&Tensor::from(0.)).item().unwrap(), 1.);
+        assert_eq!(Tensor::log(&Tensor::exp(&t)).to_vec(), vec![1., 4.]);
+        assert_eq!(Tensor::sqrt(&t).to_vec(), vec![1., 2.]);
+        assert_eq!(Tensor::rsqrt(&t).to_vec(), vec![1., 0.5]);
+        assert_eq!(Tensor::pow(&t, 1.5).to_vec(), vec![1., 8.]);
+        assert_eq!(Tensor::reciprocal(&t).to_vec(), vec![1., 0.25]);
+
+        let signed = Tensor::from(&[-2., 0., 3.] as &[f64]);
+        assert_eq!(Tensor::abs(&signed).to_vec(), vec![2., 0., 3.]);
+        assert_eq!(Tensor::sign(&signed).to_vec(), vec![-1., 0., 1.]);
+
+        let angles = Tensor::from(&[0., std::f64::consts::FRAC_PI_2] as &[f64]);
+        assert!((Tensor::sin(&angles).to_vec()[1] - 1.).abs() < 1e-12);
+        assert!(Tensor::cos(&angles).to_vec()[1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_activations() {
+        let t = Tensor::from(&[-1., 0., 2.] as &[f64]);
+        let close = |a: Tensor, b: &[f64]| {
+            std::iter::zip(a.to_vec(), b).for_each(|(a, b)| assert!((a - b).abs() < 1e-4, "{a} vs {b}"))
+        };
+
+        close(Tensor::sigmoid(&t), &[0.26894, 0.5, 0.88080]);
+        close(Tensor::tanh(&t), &[-0.76159, 0., 0.96403]);
+        close(Tensor::gelu(&t), &[-0.15881, 0., 1.95460]);
+        close(Tensor::silu(&t), &[-0.26894, 0., 1.76159]);
+        close(Tensor::leaky_relu(&t, 0.1), &[-0.1, 0., 2.]);
+        close(Tensor::elu(&t, 1.), &[-0.63212, 0., 2.]);
+        close(Tensor::softplus(&t), &[0.31326, std::f64::consts::LN_2, 2.12693]);
+
+        // nothing overflows at the extremes
+        let big = Tensor::from(&[-1000., 1000.] as &[f64]);
+        assert_eq!(Tensor::sigmoid(&big).to_vec(), vec![0., 1.]);
+        assert_eq!(Tensor::silu(&big).to_vec(), vec![-0., 1000.]);
+        assert_eq!(Tensor::softplus(&big).to_vec(), vec![0., 1000.]);
+        assert_eq!(Tensor::gelu(&big).to_vec(), vec![-0., 1000.]);
+        assert_eq!(Tensor::elu(&big, 1.).to_vec(), vec![-1., 1000.]);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let t = Tensor::new(vec![1., 2., 3., 1., 1., 1.], &[2, 3]);
+        let s = t.softmax(-1).to_vec();
+        assert!((s[0] - 0.09003).abs() < 1e-5 && (s[2] - 0.66524).abs() < 1e-5);
+        assert!(s[3..].iter().all(|v| (v - 1. / 3.).abs() < 1e-12));
+        assert_eq!(t.softmax(0).size(), &[2, 3]);
+
+        let lse = t.logsumexp(1, false);
+        assert_eq!(lse.size(), &[2]);
+        assert!((lse.to_vec()[1] - (1. + 3f64.ln())).abs() < 1e-12);
+        assert_eq!(t.logsumexp(1, true).size(), &[2, 1]);
+
+        // logits that would overflow a naive e^x
+        let big = Tensor::new(vec![1000., 1001., -1000., -1001.], &[2, 2]);
+        let s = big.softmax(1).to_vec();
+        assert!(s.iter().all(|v| v.is_finite()));
+        assert!((s[1] - 0.73106).abs() < 1e-5 && (s[2] - 0.73106).abs() < 1e-5);
+        let ls = big.log_softmax(1).to_vec();
+        assert!((ls[1] - (-0.31326)).abs() < 1e-5);
+        let lse = big.logsumexp(1, false).to_vec();
+        assert!((lse[0] - 1001.31326).abs() < 1e-5 && (lse[1] - -999.68674).abs() < 1e-5);
+
+        let neg_inf = Tensor::new(vec![f64::NEG_INFINITY, 0.], &[2]);
+        assert_eq!(neg_inf.softmax(0).to_vec(), vec![0., 1.]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let t = Tensor::new(vec![1., 2., 3., 4., 5., 6.], &[2, 3]);
+
+        let err = Tensor::add(&t, &Tensor::ones(&[4])).unwrap_err();
+        assert_eq!(err, RaxError::shape("add", &[2, 3], &[4]));
+        assert_eq!(err.to_string(), "add: incompatible shapes [2, 3] and [4]");
+        assert_eq!(t.try_matmul(&t).unwrap_err(), RaxError::shape("matmul", &[2, 3], &[2, 3]));
+        assert_eq!(t.try_reshape(&[4]).unwrap_err(), RaxError::shape("reshape", &[2, 3], &[4]));
+        assert!(t.transpose(0, 1).try_view(&[6]).is_err());
+        assert!(t.try_permute(&[0, 0]).is_err());
+        assert!(Tensor::try_cat(&[], 0).is_err());
+        assert!(Tensor::try_stack(&[&t, &Tensor::ones(&[3, 2])], 0).is_err());
+
+        assert_eq!(
+            t.at(&vec![2, 0]),
+            Err(RaxError::IndexOutOfBounds { index: vec![2, 0], shape: vec![2, 3] })
+        );
+        assert!(t.at(&vec![0]).is_err());
+        assert_eq!(t.item(), Err(RaxError::shape("item", &[2, 3], &[])));
+        assert_eq!(Tensor::ones(&[1, 1]).item(), Ok(1.));
+
+        assert_eq!(t.try_flatten(1, 0).unwrap_err(), RaxError::invalid("flatten", "start dim 1 is after end dim 0"));
+        assert!(t.try_squeeze(2).is_err());
+        assert_eq!(t.try_unsqueeze(-4).unwrap_err(), RaxError::AxisOutOfRange { axis: -4, n_dims: 3 });
+        assert!(t.try_index_select(1, &[0, 3]).is_err());
+        let index = Tensor::from_vec(vec![0i64, 2], &[2, 1]);
+        assert_eq!(t.try_gather(1, &index).unwrap().to_vec(), vec![1., 6.]);
+        let too_big = Tensor::from_vec(vec![0i64, 3], &[2, 1]);
+        assert!(t.try_gather(1, &too_big).is_err());
+        assert!(t.try_scatter_add(1, &too_big, &Tensor::ones(&[2, 1])).is_err());
+        assert_eq!(
+            t.try_gather(1, &index.to_dtype(DType::Bool)).unwrap_err(),
+            RaxError::DTypeMismatch { op: "gather", expected: DType::I64, got: DType::Bool }
+        );
+        assert_eq!(
+            t.try_gather(1, &index.to_dtype(DType::F64)).unwrap_err(),
+            RaxError::DTypeMismatch { op: "gather", expected: DType::I64, got: DType::F64 }
+        );
+        let negative = Tensor::from_vec(vec![0i64, -1], &[2, 1]);
+        assert_eq!(t.try_gather(1, &negative).unwrap_err(), RaxError::NegativeIndex { op: "gather", index: -1 });
+        assert!(t.try_scatter_add(1, &negative, &Tensor::ones(&[2, 1])).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "reshape: incompatible shapes [2, 3] and [4]")]
+    fn test_panics_with_error_message() {
+        Tensor::zeros(&[2, 3]).reshape(&[4]);
+    }
 }
@@ -0,0 +1,29 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::node::Node;
+use crate::optimizer::ParamsMap;
+use crate::tensor::Tensor;
+
+pub(crate) fn param(t: Tensor, name: &'static str) -> Rc<Node> {
+    Rc::new(Node::TensorParam(t, name))
+}
+
+/// `name`'s entry of `params` as a `TensorParam`
+pub(crate) fn param_in(params: &ParamsMap, name: &'static str) -> Rc<Node> {
+    param(params.0[name].clone(), name)
+}
+
+/// the weights of a small linear layer, 3 features in and 2 out
+pub(crate) fn params() -> ParamsMap {
+    ParamsMap(HashMap::from([
+        ("w".to_string(), Tensor::new(vec![0.5, -1., 2., 0.25, 1.5, -0.5], &[3, 2])),
+        ("b".to_string(), Tensor::new(vec![0.1, -0.3], &[2])),
+    ]))
+}
+
+pub(crate) fn assert_close(a: &Tensor, b: &Tensor) {
+    assert_eq!(a.size(), b.size());
+    for (x, y) in std::iter::zip(a.to_vec(), b.to_vec()) {
+        assert!((x - y).abs() < 1e-9, "{x} vs {y}");
+    }
+}
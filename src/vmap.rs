@@ -0,0 +1,250 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::backward::{topo_sort, GradMap};
+use crate::error::{OrPanic, RaxError};
+use crate::jvp::op_name;
+use crate::node::Node;
+use crate::ops::{expand_batch, sum};
+use crate::optimizer::ParamsMap;
+use crate::tensor::Tensor;
+
+/// Runs the per-example model `f` over a batch. `xs` holds one example per
+/// entry along its first axis, and so do the parameters named in `batched`
+/// (e.g. labels); the other parameters are shared by every example.
+///
+/// `f` is called once, on the first example, and the graph it builds is then
+/// rewritten with each op's batching rule (see `BinaryOp::batch`). So `f`
+/// should only look at the example through nodes: a tensor it computes from
+/// the example's values, like a mask for `where_`, would be the first
+/// example's for all of them.
+///
+/// The result has a batch axis in front of the shape `f` returns. Batched
+/// parameters keep their names, so `grads` works on it as usual.
+pub fn vmap(f: impl Fn(&ParamsMap, Tensor) -> Rc<Node>, params: &ParamsMap, batched: &[&str], xs: &Tensor) -> Rc<Node> {
+    try_vmap(f, params, batched, xs).or_panic()
+}
+
+/// errors if `xs` is a scalar, or a batched parameter has a different batch size
+pub fn try_vmap(
+    f: impl Fn(&ParamsMap, Tensor) -> Rc<Node>,
+    params: &ParamsMap,
+    batched: &[&str],
+    xs: &Tensor,
+) -> Result<Rc<Node>, RaxError> {
+    let Some(&n_batch) = xs.size().first() else {
+        return Err(RaxError::shape("vmap", xs.size(), &[1]));
+    };
+
+    let mut first_params = ParamsMap::new();
+    for (name, param) in &params.0 {
+        let first = if batched.contains(&name.as_str()) {
+            if param.size().first() != Some(&n_batch) {
+                return Err(RaxError::shape("vmap", xs.size(), param.size()));
+            }
+            first_example(param)
+        } else {
+            param.clone()
+        };
+        first_params.0.insert(name.clone(), first);
+    }
+    // a fresh buffer, so the example can be picked out of the graph
+    let x = first_example(xs);
+    let out = f(&first_params, x.clone());
+
+    // only the nodes that depend on the batch are rebuilt, the rest are shared
+    let mut batched_nodes: HashMap<*const Node, Rc<Node>> = HashMap::new();
+    let lookup = |nodes: &HashMap<*const Node, Rc<Node>>, arg: &Rc<Node>| nodes.get(&Rc::as_ptr(arg)).cloned();
+
+    for node in topo_sort(&out) {
+        let new = match node {
+            Node::TensorParam(_, name) if batched.contains(name) => {
+                Some(Rc::new(Node::TensorParam(params.0[*name].clone(), name)))
+            }
+            Node::TensorParam(t, name) if t.shares_buffer(&x) => {
+                check_example(t, &x)?;
+                Some(Rc::new(Node::TensorParam(xs.clone(), name)))
+            }
+            Node::Const(t) if t.shares_buffer(&x) => {
+                check_example(t, &x)?;
+                Some(Rc::new(Node::Const(xs.clone())))
+            }
+            Node::TensorParam(..) | Node::Const(_) | Node::Placeholder(_) => None,
+            Node::BinaryOp(res) => {
+                let (l, r) = (lookup(&batched_nodes, &res.args.0), lookup(&batched_nodes, &res.args.1));
+                if l.is_none() && r.is_none() {
+                    None
+                } else {
+                    let flags = (l.is_some(), r.is_some());
+                    let args = (l.unwrap_or(res.args.0.clone()), r.unwrap_or(res.args.1.clone()));
+                    Some(res.op.batch(args, flags, &node.val()))
+                }
+            }
+            Node::UnaryOp(res) => lookup(&batched_nodes, &res.arg).map(|arg| res.op.batch(arg, &node.val())),
+            Node::ReduceOp(res) => lookup(&batched_nodes, &res.arg).map(|arg| res.op.batch(arg, &node.val())),
+            Node::NaryOp(res) => {
+                let args: Vec<Option<Rc<Node>>> = res.args.iter().map(|arg| lookup(&batched_nodes, arg)).collect();
+                if args.iter().all(Option::is_none) {
+                    None
+                } else {
+                    let flags = args.iter().map(Option::is_some).collect();
+                    let args = std::iter::zip(args, &res.args).map(|(b, arg)| b.unwrap_or(arg.clone())).collect();
+                    Some(res.op.batch(args, flags, &node.val()))
+                }
+            }
+        };
+
+        if let Some(new) = new {
+            let expected = [&[n_batch], node.val().size()].concat();
+            assert_eq!(
+                new.val().size(),
+                expected,
+                "batching rule of {} gave shape {:?}, expected {:?}",
+                op_name(node),
+                new.val().size(),
+                expected
+            );
+            batched_nodes.insert(node as *const Node, new);
+        }
+    }
+
+    // an output that doesn't depend on the batch is the same for every example
+    Ok(batched_nodes
+        .remove(&Rc::as_ptr(&out))
+        .unwrap_or_else(|| expand_batch(out, n_batch)))
+}
+
+/// The gradient of `f` for each example on its own, e.g. to clip them one by
+/// one for DP-SGD. `params`, `batched` and `xs` are as for `vmap`, and each
+/// shared parameter gets a gradient with a batch axis in front. A per-example
+/// output with more than one element is summed.
+///
+/// Every example gets its own copy of the shared parameters, so a single
+/// backward pass through the batched graph keeps the examples apart.
+pub fn per_example_grads(
+    f: impl Fn(&ParamsMap, Tensor) -> Rc<Node>,
+    params: &ParamsMap,
+    batched: &[&str],
+    xs: &Tensor,
+) -> GradMap {
+    try_per_example_grads(f, params, batched, xs).or_panic()
+}
+
+/// errors where `try_vmap` does
+pub fn try_per_example_grads(
+    f: impl Fn(&ParamsMap, Tensor) -> Rc<Node>,
+    params: &ParamsMap,
+    batched: &[&str],
+    xs: &Tensor,
+) -> Result<GradMap, RaxError> {
+    let Some(&n_batch) = xs.size().first() else {
+        return Err(RaxError::shape("vmap", xs.size(), &[1]));
+    };
+    let mut copies = ParamsMap::new();
+    for (name, param) in &params.0 {
+        let param = if batched.contains(&name.as_str()) {
+            param.clone()
+        } else {
+            param.unsqueeze(0).expand(&[&[n_batch], param.size()].concat())
+        };
+        copies.0.insert(name.clone(), param);
+    }
+    let all: Vec<&str> = params.0.keys().map(String::as_str).collect();
+
+    let out = try_vmap(f, &copies, &all, xs)?;
+    let mut grads = sum(out, &[], false).grads();
+    grads.retain(|name, _| params.0.contains_key(name) && !batched.contains(&name.as_str()));
+    Ok(grads)
+}
+
+/// a copy of `t`'s first entry along its first axis
+fn first_example(t: &Tensor) -> Tensor {
+    let first = t.narrow(0, 0, 1);
+    Tensor::new_as(first.to_vec(), &t.size()[1..], t.dtype())
+}
+
+/// the example has to be handed to a node as it is, not e.g. reshaped first
+fn check_example(t: &Tensor, x: &Tensor) -> Result<(), RaxError> {
+    if t.size() != x.size() {
+        return Err(RaxError::shape("vmap", x.size(), t.size()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::losses::{mse, Reduction};
+    use crate::ops::{add, log_softmax, mmul, tanh};
+    use crate::test_util::{self, assert_close, param_in};
+
+    fn params() -> ParamsMap {
+        let mut p = test_util::params();
+        p.0.insert("y".to_string(), Tensor::new(vec![1., 0., 0., 1., 0.5, 0.5, -1., 2.], &[4, 2]));
+        p
+    }
+
+    fn xs() -> Tensor {
+        Tensor::new(vec![1., -2., 0.5, 3., 1.5, -1., 0., 0.25, 2., -0.5, -1., 1.], &[4, 3])
+    }
+
+    /// the loss of one example
+    fn model(p: &ParamsMap, x: Tensor) -> Rc<Node> {
+        let h = tanh(add(mmul(Rc::new(Node::Const(x)), param_in(p, "w")), param_in(p, "b")));
+        mse(log_softmax(h, -1), param_in(p, "y"), Reduction::Sum)
+    }
+
+    /// `p` with the batched entries replaced by example `i`'s
+    fn example(p: &ParamsMap, batched: &[&str], i: usize) -> ParamsMap {
+        let mut p = ParamsMap(p.0.clone());
+        for name in batched {
+            let t = p.0.get_mut(*name).unwrap();
+            *t = first_example(&t.narrow(0, i, 1));
+        }
+        p
+    }
+
+    #[test]
+    fn test_vmap() {
+        let out = vmap(model, &params(), &["y"], &xs());
+        assert_eq!(out.val().size(), &[4]);
+        for i in 0..4 {
+            let single = model(&example(&params(), &["y"], i), first_example(&xs().narrow(0, i, 1)));
+            assert!((out.val().to_vec()[i] - single.val().item().unwrap()).abs() < 1e-12);
+        }
+
+        // gradients flow back to the shared parameters as for a hand-batched model
+        let grads = sum(out, &[], false).grads();
+        let mut expected = Tensor::zeros(&[3, 2]);
+        for i in 0..4 {
+            let single = model(&example(&params(), &["y"], i), first_example(&xs().narrow(0, i, 1)));
+            expected = Tensor::add(&expected, &single.grads()["w"]).unwrap();
+        }
+        assert_close(&grads["w"], &expected);
+
+        // an output that ignores the example is repeated
+        let out = vmap(|p, _| tanh(param_in(p, "b")), &params(), &[], &xs());
+        assert_eq!(out.val().size(), &[4, 2]);
+        assert_eq!(out.val().narrow(0, 3, 1).to_vec(), tanh(param_in(&params(), "b")).val().to_vec());
+
+        let err = try_vmap(model, &params(), &["w"], &xs()).unwrap_err();
+        assert_eq!(err, RaxError::shape("vmap", &[4, 3], &[3, 2]));
+    }
+
+    #[test]
+    fn test_per_example_grads() {
+        let grads = per_example_grads(model, &params(), &["y"], &xs());
+        assert_eq!(grads.len(), 2);
+        assert_eq!(grads["w"].size(), &[4, 3, 2]);
+        assert_eq!(grads["b"].size(), &[4, 2]);
+
+        for i in 0..4 {
+            let single = model(&example(&params(), &["y"], i), first_example(&xs().narrow(0, i, 1))).grads();
+            for name in ["w", "b"] {
+                assert_close(&first_example(&grads[name].narrow(0, i, 1)), &single[name]);
+            }
+        }
+
+        let err = try_per_example_grads(model, &params(), &["y"], &Tensor::from(1.)).unwrap_err();
+        assert_eq!(err, RaxError::shape("vmap", &[], &[1]));
+    }
+}
@@ -1,8 +1,8 @@
-use rusty_grad::backward::{accum_grads, GradMap};
 use rusty_grad::grad;
 use rusty_grad::node::Node;
 use rusty_grad::ops::{add, mmul, relu, sqr, sub};
 use rusty_grad::optimizer::{Optimizer, ParamsMap, SGD};
+use rusty_grad::random;
 use rusty_grad::tensor::Tensor;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -23,9 +23,7 @@ fn test_train_mlp() {
 
         let x1 = relu(add(mmul(input, w1), b1));
         let x2 = relu(add(mmul(x1, w2), b2));
-        let x3 = relu(add(mmul(x2, w3), b3));
-
-        x3
+        relu(add(mmul(x2, w3), b3))
     }
 
     fn forward(params: &ParamsMap, input: Tensor, label: Tensor) -> Rc<Node> {
@@ -36,19 +34,20 @@ fn test_train_mlp() {
     }
 
     fn train_model() -> ParamsMap {
+        random::manual_seed(0);
         let mut params = ParamsMap(HashMap::from([
-            ("w1".to_string(), Tensor::rand(&vec![3, 4])),
-            ("b1".to_string(), Tensor::rand(&vec![4])),
-            ("w2".to_string(), Tensor::rand(&vec![4, 3])),
-            ("b2".to_string(), Tensor::rand(&vec![3])),
-            ("w3".to_string(), Tensor::rand(&vec![3, 1])),
-            ("b3".to_string(), Tensor::rand(&vec![1])),
+            ("w1".to_string(), Tensor::rand(&[3, 4])),
+            ("b1".to_string(), Tensor::rand(&[4])),
+            ("w2".to_string(), Tensor::rand(&[4, 3])),
+            ("b2".to_string(), Tensor::rand(&[3])),
+            ("w3".to_string(), Tensor::rand(&[3, 1])),
+            ("b3".to_string(), Tensor::rand(&[1])),
         ]));
 
         let optim = SGD::default();
 
-        let x = Tensor::rand(&vec![1, 3]);
-        let y = Tensor::rand(&vec![1, 1]);
+        let x = Tensor::rand(&[1, 3]);
+        let y = Tensor::rand(&[1, 1]);
 
         for i in 0.. {
             let (loss, grads_map) = grad!(forward, &params, x.clone(), y.clone());
@@ -82,9 +81,10 @@ fn test_train_simple() {
     }
 
     fn train_model() -> ParamsMap {
+        random::manual_seed(0);
         let mut params = ParamsMap(HashMap::from([
-            ("a".to_string(), Tensor::rand(&vec![1])),
-            ("b".to_string(), Tensor::rand(&vec![1])),
+            ("a".to_string(), Tensor::rand(&[1])),
+            ("b".to_string(), Tensor::rand(&[1])),
         ]));
 
         let optim = SGD::default();
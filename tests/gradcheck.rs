@@ -0,0 +1,433 @@
+//! checks the gradient of every op in `ops` against central finite differences
+use rusty_grad::backward::{accum_grads, GradMap};
+use rusty_grad::jvp::jvp;
+use rusty_grad::lazy::{lazy, placeholder, Feeds};
+use rusty_grad::node::Node;
+use rusty_grad::ops::{
+    abs, add, add_scalar, cat, chunk, cos, div, elu, exp, expand, flatten, gather, gelu, index_select, leaky_relu, log,
+    log_softmax, logsumexp, masked_select, max, maximum, mean, min, minimum, mmul, mul, mul_scalar, neg, permute, pow,
+    pow_tensor, prod, reciprocal, relu, reshape, rsqrt, scatter_add, sigmoid, silu, sin, slice, softmax, softplus,
+    split, sqr, sqrt, squeeze, stack, sub, sum, tanh, transpose, unsqueeze, view, where_, xlogx,
+};
+use rusty_grad::optimizer::ParamsMap;
+use rusty_grad::tensor::{SliceRange, Tensor};
+use rusty_grad::vmap::vmap;
+use std::rc::Rc;
+
+const NAMES: [&str; 3] = ["x0", "x1", "x2"];
+/// the vectors of the Hessian-vector products
+const V_NAMES: [&str; 3] = ["v0", "v1", "v2"];
+const EPS: f64 = 1e-6;
+const TOL: f64 = 1e-5;
+
+/// deterministic, distinct values in roughly [-2, 2]
+fn t(shape: &[usize], seed: f64) -> Tensor {
+    let n = shape.iter().product();
+    let data = (0..n).map(|i| 2. * (i as f64 * 1.37 + seed).sin()).collect();
+    Tensor::new(data, shape)
+}
+
+/// deterministic, distinct values in roughly [0.5, 2.5]
+fn pos(shape: &[usize], seed: f64) -> Tensor {
+    Tensor::new(t(shape, seed).to_vec().iter().map(|x| x / 2. + 1.5).collect(), shape)
+}
+
+/// fixed weights so the scalar loss depends on every output element differently
+fn weights(shape: &[usize]) -> Tensor {
+    let n = shape.iter().product();
+    Tensor::new((0..n).map(|i| 1. + 0.25 * i as f64).collect(), shape)
+}
+
+fn params(inputs: &[Tensor]) -> Vec<Rc<Node>> {
+    inputs
+        .iter()
+        .zip(NAMES)
+        .map(|(x, name)| Rc::new(Node::TensorParam(x.clone(), name)))
+        .collect()
+}
+
+/// the loss is `sum(f(inputs) * weights)`
+fn loss_value(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) -> f64 {
+    let out = f(&params(inputs)).val();
+    let w = weights(out.size());
+    std::iter::zip(out.to_vec(), w.to_vec()).map(|(o, w)| o * w).sum()
+}
+
+fn loss(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) -> Rc<Node> {
+    let out = f(&params(inputs));
+    let w = Rc::new(Node::TensorParam(weights(out.val().size()), "w"));
+    sum(mul(out, w), &[], false)
+}
+
+/// the gradients' directional derivative `sum_k grad_k . weights`, the scalar
+/// whose gradient is a Hessian-vector product
+fn grad_dot_weights(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) -> f64 {
+    let grads = loss(f, inputs).grads();
+    (0..inputs.len())
+        .map(|k| {
+            let g = grads[NAMES[k]].to_vec();
+            std::iter::zip(g, weights(inputs[k].size()).to_vec()).map(|(g, w)| g * w).sum::<f64>()
+        })
+        .sum()
+}
+
+/// central differences of `value` with respect to `inputs[k]`
+fn numeric_grad(value: impl Fn(&[Tensor]) -> f64, inputs: &[Tensor], k: usize) -> Vec<f64> {
+    let x = &inputs[k];
+    let data = x.to_vec();
+    (0..data.len())
+        .map(|i| {
+            let mut inputs_hi = inputs.to_vec();
+            let mut inputs_lo = inputs.to_vec();
+            let (mut hi, mut lo) = (data.clone(), data.clone());
+            hi[i] += EPS;
+            lo[i] -= EPS;
+            inputs_hi[k] = Tensor::new(hi, x.size());
+            inputs_lo[k] = Tensor::new(lo, x.size());
+            (value(&inputs_hi) - value(&inputs_lo)) / (2. * EPS)
+        })
+        .collect()
+}
+
+/// compares the analytic gradient of each input, from the DAG pass, the tree
+/// backward pass and the gradient graph, against central differences, then
+/// checks the gradient graph's own gradient (see `check_hvp`), the forward
+/// mode tangents (see `check_jvp`), the batching rules (see `check_vmap`) and
+/// the shape rules (see `check_lazy`)
+fn check_grads(f: impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    check_first_order(&f, inputs);
+    check_hvp(&f, inputs);
+    check_jvp(&f, inputs);
+    check_vmap(&f, inputs);
+    check_lazy(&f, inputs);
+}
+
+fn check_first_order(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    let loss = loss(f, inputs);
+    let dag = loss.grads();
+    let tree = accum_grads(loss.backwards());
+    let graph: GradMap = loss.grad_nodes().iter().map(|(name, g)| (name.clone(), g.val())).collect();
+
+    for (k, x) in inputs.iter().enumerate() {
+        let name = NAMES[k];
+        let numeric = numeric_grad(|inputs| loss_value(f, inputs), inputs, k);
+
+        for (pass, grads) in [("grads", &dag), ("backwards", &tree), ("grad_nodes", &graph)] {
+            let g = grads.get(name).unwrap_or_else(|| panic!("{pass}: no gradient for {name}"));
+            assert_eq!(g.size(), x.size(), "{pass}: gradient shape for {name}");
+            for (i, (a, n)) in std::iter::zip(g.to_vec(), &numeric).enumerate() {
+                assert!(
+                    (a - n).abs() <= TOL * (1. + n.abs()),
+                    "{pass}: d/d{name}[{i}] analytic {a} vs numeric {n}"
+                );
+            }
+        }
+    }
+}
+
+/// compares the forward mode derivative of `f` along a direction moving every
+/// input at once against a central difference along that direction
+fn check_jvp(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    let directions: Vec<Tensor> = inputs.iter().enumerate().map(|(k, x)| t(x.size(), 10. + k as f64)).collect();
+    let step = |h: f64| -> Vec<Tensor> {
+        std::iter::zip(inputs, &directions)
+            .map(|(x, d)| Tensor::add(x, &Tensor::mul(d, &Tensor::from(h)).unwrap()).unwrap())
+            .collect()
+    };
+    let (hi, lo) = (f(&params(&step(EPS))).val(), f(&params(&step(-EPS))).val());
+    let numeric: Vec<f64> = std::iter::zip(hi.to_vec(), lo.to_vec()).map(|(h, l)| (h - l) / (2. * EPS)).collect();
+
+    let (val, tangent) = jvp(|x| f(x), inputs, &directions);
+    assert_eq!(val.to_vec(), f(&params(inputs)).val().to_vec());
+    assert_eq!(tangent.size(), val.size(), "jvp: tangent shape");
+    for (i, (a, n)) in std::iter::zip(tangent.to_vec(), &numeric).enumerate() {
+        assert!((a - n).abs() <= TOL * (1. + n.abs()), "jvp: [{i}] analytic {a} vs numeric {n}");
+    }
+}
+
+/// runs `f` under `vmap` on a batch of inputs, once with every input batched
+/// and once with only the first, and compares each example against `f` on it
+fn check_vmap(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    const N_BATCH: usize = 3;
+    // example `b` moves every input away from `inputs` a little, keeping its sign
+    let example = |b: usize| -> Vec<Tensor> {
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(k, x)| {
+                let shift = t(x.size(), 20. + (b * inputs.len() + k) as f64);
+                let data = std::iter::zip(x.to_vec(), shift.to_vec()).map(|(x, s)| x * (1. + 0.05 * s)).collect();
+                Tensor::new(data, x.size())
+            })
+            .collect()
+    };
+    let stacked = |k: usize| -> Tensor {
+        let data = (0..N_BATCH).flat_map(|b| example(b)[k].to_vec()).collect();
+        Tensor::new(data, &[&[N_BATCH], inputs[k].size()].concat())
+    };
+
+    for n_batched in [inputs.len(), 1] {
+        let mut others = ParamsMap::new();
+        for (k, name) in NAMES.iter().enumerate().take(inputs.len()).skip(1) {
+            let x = if k < n_batched { stacked(k) } else { example(0)[k].clone() };
+            others.0.insert(name.to_string(), x);
+        }
+        let model = |p: &ParamsMap, x0: Tensor| {
+            let mut xs = vec![Rc::new(Node::TensorParam(x0, NAMES[0]))];
+            xs.extend((1..inputs.len()).map(|k| Rc::new(Node::TensorParam(p.0[NAMES[k]].clone(), NAMES[k]))));
+            f(&xs)
+        };
+        let out = vmap(model, &others, &NAMES[1..n_batched], &stacked(0)).val();
+
+        for b in 0..N_BATCH {
+            let mut single = example(b);
+            single[n_batched..].clone_from_slice(&example(0)[n_batched..]);
+            let expected = f(&params(&single)).val();
+            let row = out.narrow(0, b, 1);
+            assert_eq!(row.size()[1..], *expected.size(), "vmap: output shape");
+            for (i, (a, e)) in std::iter::zip(row.to_vec(), expected.to_vec()).enumerate() {
+                assert!((a - e).abs() <= 1e-12 * (1. + e.abs()), "vmap ({n_batched} batched): [{b}, {i}] {a} vs {e}");
+            }
+        }
+    }
+}
+
+/// builds `f` lazily on placeholders, checks the shape worked out for its
+/// output, then evaluates it on two sets of inputs and compares the values and
+/// gradients against `f` built on them directly
+fn check_lazy(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    let placeholders: Vec<Rc<Node>> = std::iter::zip(inputs, NAMES).map(|(x, name)| placeholder(name, x.size())).collect();
+    let eager = loss(f, inputs);
+    let graph = lazy(|| {
+        let out = f(&placeholders);
+        let w = Rc::new(Node::TensorParam(weights(out.shape()), "w"));
+        sum(mul(out, w), &[], false)
+    });
+    assert_eq!(graph.args()[0].args()[0].shape(), f(&params(inputs)).val().size(), "lazy: output shape");
+
+    let shifted: Vec<Tensor> = inputs
+        .iter()
+        .enumerate()
+        .map(|(k, x)| Tensor::new(x.to_vec().iter().map(|v| v * (1. + 0.01 * k as f64) + 0.001).collect(), x.size()))
+        .collect();
+    for (inputs, eager) in [(inputs.to_vec(), eager), (shifted.clone(), loss(f, &shifted))] {
+        let feeds: Feeds = std::iter::zip(NAMES, &inputs).map(|(name, x)| (name.to_string(), x.clone())).collect();
+        assert_eq!(graph.eval(&feeds).to_vec(), eager.val().to_vec(), "lazy: value");
+        let (grads, expected) = (graph.grads(), eager.grads());
+        for name in NAMES.iter().take(inputs.len()) {
+            assert_eq!(grads[*name].to_vec(), expected[*name].to_vec(), "lazy: gradient of {name}");
+        }
+    }
+}
+
+/// differentiates the gradient graph again, and compares the Hessian-vector
+/// product that gives against differences of the first order gradients
+fn check_hvp(f: &impl Fn(&[Rc<Node>]) -> Rc<Node>, inputs: &[Tensor]) {
+    let grad_nodes = loss(f, inputs).grad_nodes();
+    let hvp = (0..inputs.len())
+        .map(|k| {
+            let v = Rc::new(Node::TensorParam(weights(inputs[k].size()), V_NAMES[k]));
+            sum(mul(grad_nodes[NAMES[k]].clone(), v), &[], false)
+        })
+        .reduce(add)
+        .unwrap()
+        .grads();
+
+    for (k, name) in NAMES.iter().enumerate().take(inputs.len()) {
+        let numeric = numeric_grad(|inputs| grad_dot_weights(f, inputs), inputs, k);
+        // a gradient that doesn't depend on `name` at all has no entry
+        let analytic = hvp.get(*name).map_or(vec![0.; numeric.len()], |g| g.to_vec());
+        for (i, (a, n)) in std::iter::zip(analytic, &numeric).enumerate() {
+            assert!(
+                (a - n).abs() <= TOL * (1. + n.abs()),
+                "hessian-vector product: d/d{name}[{i}] analytic {a} vs numeric {n}"
+            );
+        }
+    }
+}
+
+#[test]
+fn binary() {
+    check_grads(|x| add(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| sub(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| mul(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| div(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), pos(&[2, 3], 1.)]);
+    check_grads(|x| maximum(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| minimum(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| pow_tensor(x[0].clone(), x[1].clone()), &[pos(&[2, 3], 0.), t(&[2, 3], 1.)]);
+}
+
+#[test]
+fn binary_broadcast() {
+    check_grads(|x| add(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[3], 1.)]);
+    check_grads(|x| sub(x[0].clone(), x[1].clone()), &[t(&[2, 1], 0.), t(&[1, 3], 1.)]);
+    check_grads(|x| mul(x[0].clone(), x[1].clone()), &[t(&[4, 2, 3], 0.), t(&[2, 1], 1.)]);
+    check_grads(|x| div(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), pos(&[3], 1.)]);
+    check_grads(|x| maximum(x[0].clone(), x[1].clone()), &[t(&[2, 1], 0.), t(&[1, 3], 1.)]);
+    check_grads(|x| minimum(x[0].clone(), x[1].clone()), &[t(&[4, 2, 3], 0.), t(&[2, 1], 1.)]);
+    check_grads(|x| pow_tensor(x[0].clone(), x[1].clone()), &[pos(&[3], 0.), t(&[2, 3], 1.)]);
+}
+
+#[test]
+fn matmul() {
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[3, 4], 1.)]);
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[3], 0.), t(&[3, 4], 1.)]);
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[3], 1.)]);
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[3], 0.), t(&[3], 1.)]);
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[2, 2, 3], 0.), t(&[3, 2], 1.)]);
+    check_grads(|x| mmul(x[0].clone(), x[1].clone()), &[t(&[2, 1, 2, 3], 0.), t(&[3, 3, 2], 1.)]);
+}
+
+#[test]
+fn unary() {
+    let x = [t(&[2, 3], 0.5)];
+    check_grads(|x| sqr(x[0].clone()), &x);
+    check_grads(|x| neg(x[0].clone()), &x);
+    check_grads(|x| relu(x[0].clone()), &x);
+    check_grads(|x| exp(x[0].clone()), &x);
+    check_grads(|x| abs(x[0].clone()), &x);
+    check_grads(|x| sin(x[0].clone()), &x);
+    check_grads(|x| cos(x[0].clone()), &x);
+    check_grads(|x| pow(x[0].clone(), 3.), &x);
+    check_grads(|x| mul_scalar(x[0].clone(), -2.5), &x);
+    check_grads(|x| add_scalar(x[0].clone(), 4.), &x);
+}
+
+#[test]
+fn unary_positive_domain() {
+    let x = [pos(&[2, 3], 0.5)];
+    check_grads(|x| log(x[0].clone()), &x);
+    check_grads(|x| sqrt(x[0].clone()), &x);
+    check_grads(|x| rsqrt(x[0].clone()), &x);
+    check_grads(|x| reciprocal(x[0].clone()), &x);
+    check_grads(|x| pow(x[0].clone(), -1.5), &x);
+    check_grads(|x| pow(x[0].clone(), 0.5), &x);
+    check_grads(|x| xlogx(x[0].clone()), &x);
+}
+
+#[test]
+fn activations() {
+    let x = [t(&[2, 3], 0.5)];
+    check_grads(|x| sigmoid(x[0].clone()), &x);
+    check_grads(|x| tanh(x[0].clone()), &x);
+    check_grads(|x| gelu(x[0].clone()), &x);
+    check_grads(|x| silu(x[0].clone()), &x);
+    check_grads(|x| leaky_relu(x[0].clone(), 0.1), &x);
+    check_grads(|x| elu(x[0].clone(), 1.5), &x);
+    check_grads(|x| elu(x[0].clone(), -0.5), &x);
+    check_grads(|x| softplus(x[0].clone()), &x);
+}
+
+#[test]
+fn softmax_family() {
+    let x = [t(&[3, 4], 0.)];
+    for dim in [0, 1, -1] {
+        check_grads(|x| softmax(x[0].clone(), dim), &x);
+        check_grads(|x| log_softmax(x[0].clone(), dim), &x);
+        check_grads(|x| logsumexp(x[0].clone(), dim), &x);
+    }
+    // large logits go through the same stable path
+    let big = [Tensor::new(t(&[2, 3], 0.).to_vec().iter().map(|v| 500. * v).collect(), &[2, 3])];
+    check_grads(|x| log_softmax(x[0].clone(), 1), &big);
+    check_grads(|x| logsumexp(x[0].clone(), 1), &big);
+}
+
+#[test]
+fn reductions() {
+    let x = [t(&[2, 3, 4], 0.)];
+    for (axes, keepdim) in [(&[][..], false), (&[1][..], false), (&[0, 2][..], true), (&[-1][..], true)] {
+        check_grads(|x| sum(x[0].clone(), axes, keepdim), &x);
+        check_grads(|x| mean(x[0].clone(), axes, keepdim), &x);
+        check_grads(|x| max(x[0].clone(), axes, keepdim), &x);
+        check_grads(|x| min(x[0].clone(), axes, keepdim), &x);
+        // x[0] is 0, where prod's gradient graph is only right to first order
+        check_first_order(&|x: &[Rc<Node>]| prod(x[0].clone(), axes, keepdim), &x);
+        check_grads(|x| prod(x[0].clone(), axes, keepdim), &[t(&[2, 3, 4], 0.5)]);
+    }
+}
+
+#[test]
+fn shape() {
+    let x = [t(&[2, 3, 4], 0.)];
+    check_grads(|x| reshape(x[0].clone(), &[4, 6]), &x);
+    check_grads(|x| view(x[0].clone(), &[6, 4]), &x);
+    check_grads(|x| flatten(x[0].clone(), 1, -1), &x);
+    check_grads(|x| unsqueeze(x[0].clone(), 1), &x);
+    check_grads(|x| permute(x[0].clone(), &[2, 0, 1]), &x);
+    check_grads(|x| transpose(x[0].clone(), 0, -1), &x);
+    check_grads(|x| squeeze(x[0].clone(), 1), &[t(&[3, 1, 2], 0.)]);
+    check_grads(|x| expand(x[0].clone(), &[2, 3, 4]), &[t(&[3, 1], 0.)]);
+    // a view of a non-contiguous value
+    check_grads(|x| reshape(transpose(x[0].clone(), 0, 1), &[12]), &[t(&[3, 4], 0.)]);
+}
+
+#[test]
+fn indexing() {
+    let x = [t(&[4, 5], 0.)];
+    check_grads(|x| slice(x[0].clone(), &[(1..3).into(), SliceRange::from(..).step_by(2)]), &x);
+    check_grads(|x| index_select(x[0].clone(), 1, &[3, 0, 3]), &x);
+
+    let index = Tensor::from_vec(vec![0i64, 2, 2, 1, 0, 1], &[3, 2]);
+    check_grads(|x| gather(x[0].clone(), 0, &index), &[t(&[3, 2], 0.)]);
+
+    let mask = Tensor::new(vec![1., 0., 0., 1., 1., 0.], &[2, 3]);
+    check_grads(|x| masked_select(x[0].clone(), &mask), &[t(&[2, 3], 0.)]);
+
+    let index = Tensor::from_vec(vec![1i64, 0, 1, 1], &[2, 2]);
+    check_grads(
+        |x| scatter_add(x[0].clone(), 0, &index, x[1].clone()),
+        &[t(&[2, 2], 0.), t(&[2, 2], 1.)],
+    );
+}
+
+#[test]
+fn where_select() {
+    let cond = Tensor::new(vec![1., 0., 0., 1., 1., 0.], &[2, 3]);
+    check_grads(|x| where_(&cond, x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    // both branches broadcast up to the condition
+    check_grads(|x| where_(&cond, x[0].clone(), x[1].clone()), &[t(&[3], 0.), t(&[2, 1], 1.)]);
+    // a condition that broadcasts over the branches
+    let cond = Tensor::new(vec![1., 0.], &[2, 1, 1]);
+    check_grads(|x| where_(&cond, x[0].clone(), x[1].clone()), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+}
+
+#[test]
+fn joining() {
+    let xs = [t(&[2, 3], 0.), t(&[2, 1], 1.), t(&[2, 2], 2.)];
+    check_grads(|x| cat(x, 1), &xs);
+    check_grads(|x| cat(&x[..2], -1), &xs[..2]);
+    check_grads(|x| stack(x, 0), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+    check_grads(|x| stack(x, -1), &[t(&[2, 3], 0.), t(&[2, 3], 1.)]);
+}
+
+#[test]
+fn splitting() {
+    let x = [t(&[5, 2], 0.)];
+    // weigh the pieces differently so a misrouted gradient shows up
+    check_grads(
+        |x| {
+            let parts = split(x[0].clone(), 2, 0);
+            add(sum(mul(parts[0].clone(), parts[1].clone()), &[], false), sum(sqr(parts[2].clone()), &[], false))
+        },
+        &x,
+    );
+    check_grads(
+        |x| {
+            let parts = chunk(x[0].clone(), 2, 1);
+            sub(parts[0].clone(), exp(parts[1].clone()))
+        },
+        &x,
+    );
+}
+
+#[test]
+fn composite() {
+    // a shared subexpression used along several paths
+    check_grads(
+        |x| {
+            let h = relu(add(mmul(x[0].clone(), x[1].clone()), x[2].clone()));
+            mul(h.clone(), sqr(h))
+        },
+        &[t(&[2, 3], 0.), t(&[3, 4], 1.), t(&[4], 2.)],
+    );
+}